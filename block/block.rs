@@ -1,39 +1,279 @@
+use crate::codec::codec::{Decode, DecodeError, Decoder, Encode, Encoder};
 use crate::poh::entry::PohEntry;
-use chrono::Utc;
-use sha2::{Sha256, Digest};
-use std::fmt::Write;
+use crate::poh::hash::Hash;
+use crate::poh::merkle::{self, MerkleProof, MerkleTree};
+use crate::shard::shard::Transaction;
+use serde::{Deserialize, Serialize};
 
+// Everything a node needs to follow and check the chain without the entries.
+// `block_hash` is computed over the header alone; the body is tied to it by
+// the PoH start/end hashes and the transaction root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub block_number: u64,
+    pub previous_hash: Hash,
+    pub shard_id: usize,
+    pub epoch: usize,
+    pub proposer: usize,        // id of the validator that produced the block
+    pub poh_start_hash: Hash,   // PoH hash the first entry extends
+    pub poh_end_hash: Hash,     // hash of the last entry
+    pub transactions_root: Hash, // Merkle root over every transaction's canonical hash, in entry order
+    pub state_root: Hash,       // ledger state after the block
+    pub timestamp: i64,
+}
+
+impl BlockHeader {
+    // The PoH and transaction fields are filled in from the body by `Block::new`.
+    pub fn new(
+        block_number: u64,
+        previous_hash: Hash,
+        shard_id: usize,
+        epoch: usize,
+        proposer: usize,
+        state_root: Hash,
+        timestamp: i64,
+    ) -> Self {
+        BlockHeader {
+            block_number,
+            previous_hash,
+            shard_id,
+            epoch,
+            proposer,
+            poh_start_hash: Hash::default(),
+            poh_end_hash: Hash::default(),
+            transactions_root: Hash::default(),
+            state_root,
+            timestamp,
+        }
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.canonical_hash()
+    }
+}
+
+// The entries record transaction ids in PoH order; `transactions` carries the
+// full transactions in that same order so the block can be executed.
 #[derive(Debug, Clone)]
 pub struct Block {
-    pub block_number: u64,
+    pub header: BlockHeader,
     pub poh_entries: Vec<PohEntry>,
-    pub previous_hash: String,
-    pub block_hash: String,
-    pub timestamp: i64,  
+    pub transactions: Vec<Transaction>,
+    pub block_hash: Hash,
 }
 
 impl Block {
-    pub fn new(block_number: u64, poh_entries: Vec<PohEntry>, previous_hash: &str) -> Self {
-        let mut hasher = Sha256::new();
-        hasher.update(block_number.to_string());
-        for entry in &poh_entries {
-            hasher.update(&entry.hash);
-        }
-        hasher.update(previous_hash);
-        let result = hasher.finalize();
-        let mut hash_str = String::new();
-        for byte in result {
-            write!(&mut hash_str, "{:02x}", byte).expect("Unable to write");
-        }
-
-        let timestamp = Utc::now().timestamp();  
+    pub fn new(
+        mut header: BlockHeader,
+        poh_start_hash: Hash,
+        poh_entries: Vec<PohEntry>,
+        transactions: Vec<Transaction>,
+    ) -> Self {
+        header.poh_start_hash = poh_start_hash;
+        header.poh_end_hash = poh_entries.last().map(|entry| entry.hash).unwrap_or(poh_start_hash);
+        header.transactions_root = transactions_root(&transactions);
 
+        let block_hash = header.hash();
         Block {
-            block_number,
+            header,
             poh_entries,
-            previous_hash: previous_hash.to_string(),
-            block_hash: hash_str,
-            timestamp,  
+            transactions,
+            block_hash,
         }
     }
+
+    pub fn transaction_count(&self) -> usize {
+        self.poh_entries.iter().map(|entry| entry.transactions.len()).sum()
+    }
+
+    // Proof that `tx_id` is in this block, if it is.
+    pub fn prove_transaction(&self, tx_id: &str) -> Option<InclusionProof> {
+        let index = self.transactions.iter().position(|tx| tx.id == tx_id)?;
+        let leaves = transaction_hashes(&self.transactions);
+        Some(InclusionProof {
+            tx_id: tx_id.to_string(),
+            tx_hash: leaves[index],
+            header: self.header.clone(),
+            proof: MerkleTree::new(&leaves).prove(index)?,
+        })
+    }
+}
+
+// Leaves are the hash of each transaction's canonical encoding, so a proof
+// pins down every field of the transaction, not just its id.
+fn transaction_hashes(transactions: &[Transaction]) -> Vec<Hash> {
+    transactions.iter().map(Encode::canonical_hash).collect()
+}
+
+pub fn transactions_root(transactions: &[Transaction]) -> Hash {
+    merkle::root(&transaction_hashes(transactions))
+}
+
+// Shows that the transaction hashing to `tx_hash` was recorded in the block
+// whose header is `header`: the Merkle path from that hash up to the header's
+// transaction root. Checked against a block hash the verifier already
+// trusts, such as one from a checkpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub tx_id: String,
+    pub tx_hash: Hash, // canonical hash of the whole transaction
+    pub header: BlockHeader,
+    pub proof: MerkleProof,
+}
+
+impl InclusionProof {
+    pub fn verify(&self, block_hash: &Hash) -> bool {
+        self.header.hash() == *block_hash && self.proof.verify(self.tx_hash.as_ref(), &self.header.transactions_root)
+    }
+
+    // Whether this is a proof for `tx` exactly as given.
+    pub fn covers(&self, tx: &Transaction) -> bool {
+        self.tx_id == tx.id && self.tx_hash == tx.canonical_hash()
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.block_number);
+        encoder.put_hash(&self.previous_hash);
+        encoder.put_usize(self.shard_id);
+        encoder.put_usize(self.epoch);
+        encoder.put_usize(self.proposer);
+        encoder.put_hash(&self.poh_start_hash);
+        encoder.put_hash(&self.poh_end_hash);
+        encoder.put_hash(&self.transactions_root);
+        encoder.put_hash(&self.state_root);
+        encoder.put_i64(self.timestamp);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(BlockHeader {
+            block_number: decoder.u64()?,
+            previous_hash: decoder.hash()?,
+            shard_id: decoder.usize()?,
+            epoch: decoder.usize()?,
+            proposer: decoder.usize()?,
+            poh_start_hash: decoder.hash()?,
+            poh_end_hash: decoder.hash()?,
+            transactions_root: decoder.hash()?,
+            state_root: decoder.hash()?,
+            timestamp: decoder.i64()?,
+        })
+    }
+}
+
+impl Encode for InclusionProof {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_str(&self.tx_id);
+        encoder.put_hash(&self.tx_hash);
+        self.header.encode(encoder);
+        self.proof.encode(encoder);
+    }
+}
+
+impl Decode for InclusionProof {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(InclusionProof {
+            tx_id: decoder.string()?,
+            tx_hash: decoder.hash()?,
+            header: BlockHeader::decode(decoder)?,
+            proof: MerkleProof::decode(decoder)?,
+        })
+    }
+}
+
+// `block_hash` is not sent; the receiver recomputes it from the header.
+impl Encode for Block {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.header.encode(encoder);
+        encoder.put_seq(self.poh_entries.iter());
+        encoder.put_seq(self.transactions.iter());
+    }
+}
+
+impl Decode for Block {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode(decoder)?;
+        let poh_entries = decoder.seq()?;
+        let transactions = decoder.seq()?;
+        let block_hash = header.hash();
+        Ok(Block {
+            header,
+            poh_entries,
+            transactions,
+            block_hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys;
+    use crate::poh::clock::MockClock;
+    use crate::poh::config::PohConfig;
+    use crate::poh::generator::PohGenerator;
+    use crate::shard::shard::TransactionStatus;
+    use chrono::{TimeZone, Utc};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn transfer(nonce: u64) -> Transaction {
+        let keypair = keys::test_keypair("alice");
+        let mut tx = Transaction {
+            id: format!("tx-{}", nonce),
+            from_account: keypair.address(),
+            to_account: "bob".to_string(),
+            amount: 10,
+            fee: 1,
+            nonce,
+            from_shard: 1,
+            to_shard: 1,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Pending,
+        };
+        tx.sign(&keypair);
+        tx
+    }
+
+    fn block() -> Block {
+        let clock = Arc::new(MockClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()));
+        let mut generator = PohGenerator::with_config(2, PohConfig::default(), clock);
+        let transactions: Vec<Transaction> = (0..3).map(transfer).collect();
+        let ids = transactions.iter().map(|tx| tx.id.clone()).collect();
+        let entries = generator.generate_entries(ids, HashMap::new()).unwrap();
+        let header = BlockHeader::new(1, Hash::default(), 1, 0, 0, Hash::default(), 1_700_000_000);
+        Block::new(header, Hash::default(), entries, transactions)
+    }
+
+    #[test]
+    fn block_round_trips() {
+        let block = block();
+        let decoded = Block::from_bytes(&block.to_bytes()).unwrap();
+        assert_eq!(decoded.block_hash, block.block_hash);
+        assert_eq!(decoded.header, block.header);
+        assert_eq!(decoded.to_bytes(), block.to_bytes());
+    }
+
+    #[test]
+    fn inclusion_proof_covers_only_the_recorded_transaction() {
+        let block = block();
+        let proof = block.prove_transaction("tx-1").unwrap();
+        assert!(proof.verify(&block.block_hash));
+        assert!(proof.covers(&block.transactions[1]));
+        assert!(!proof.covers(&block.transactions[2]));
+        assert!(block.prove_transaction("tx-9").is_none());
+
+        let mut tampered = block.transactions[1].clone();
+        tampered.amount += 1;
+        assert!(!proof.covers(&tampered));
+
+        let mut other = block.header.clone();
+        other.block_number += 1;
+        assert!(!proof.verify(&other.hash()));
+        assert_eq!(InclusionProof::from_bytes(&proof.to_bytes()), Ok(proof));
+    }
 }
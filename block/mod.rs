@@ -1 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod block;
+pub mod tree;
+pub mod verifier;
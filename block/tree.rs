@@ -0,0 +1,181 @@
+use crate::block::block::Block;
+use crate::block::verifier::ChainTip;
+use crate::poh::hash::Hash;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// How competing branches are ranked. Whichever rule is used, ties fall to the
+// other measure and then to the lower head hash, so every node picks the same
+// head from the same blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ForkChoice {
+    #[default]
+    HeaviestWeight, // most proposer vote weight summed along the branch
+    LongestPoh,     // most PoH hashes since the root
+}
+
+impl FromStr for ForkChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "heaviest-weight" => Ok(ForkChoice::HeaviestWeight),
+            "longest-poh" => Ok(ForkChoice::LongestPoh),
+            other => Err(format!("unknown fork-choice rule '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TreeNode {
+    block: Block,
+    weight: f64,    // summed from the root down to this block
+    poh_hashes: u64, // likewise
+}
+
+// Every block known for a shard that descends from `root`, whether or not it
+// is on the chosen chain.
+#[derive(Debug, Clone)]
+pub struct BlockTree {
+    root: ChainTip,
+    nodes: HashMap<Hash, TreeNode>,
+    children: HashMap<Hash, Vec<Hash>>,
+    fork_choice: ForkChoice,
+}
+
+impl BlockTree {
+    pub fn new(root: ChainTip, fork_choice: ForkChoice) -> Self {
+        BlockTree {
+            root,
+            nodes: HashMap::new(),
+            children: HashMap::new(),
+            fork_choice,
+        }
+    }
+
+    // Rank branches by `fork_choice` from now on. Nodes keep both measures,
+    // so the tree needs no rebuilding.
+    pub fn set_fork_choice(&mut self, fork_choice: ForkChoice) {
+        self.fork_choice = fork_choice;
+    }
+
+    pub fn get(&self, block_hash: &Hash) -> Option<&Block> {
+        self.nodes.get(block_hash).map(|node| &node.block)
+    }
+
+    // The tip a child of `block_hash` has to extend, if that block is the
+    // root or in the tree.
+    pub fn tip_of(&self, block_hash: &Hash) -> Option<ChainTip> {
+        if *block_hash == self.root.block_hash {
+            return Some(self.root);
+        }
+        self.nodes.get(block_hash).map(|node| ChainTip::of(&node.block))
+    }
+
+    // Add a block whose parent is the root or already in the tree. `weight`
+    // is what the block itself counts for under `HeaviestWeight`. Returns
+    // false, leaving the tree unchanged, if the parent is unknown.
+    pub fn insert(&mut self, block: Block, weight: f64) -> bool {
+        let parent = block.header.previous_hash;
+        let (parent_weight, parent_hashes) = if parent == self.root.block_hash {
+            (0.0, 0)
+        } else {
+            match self.nodes.get(&parent) {
+                Some(node) => (node.weight, node.poh_hashes),
+                None => return false,
+            }
+        };
+        if self.nodes.contains_key(&block.block_hash) {
+            return true;
+        }
+
+        let poh_hashes: u64 = block.poh_entries.iter().map(|entry| entry.num_hashes).sum();
+        let block_hash = block.block_hash;
+        self.children.entry(parent).or_default().push(block_hash);
+        self.nodes.insert(
+            block_hash,
+            TreeNode {
+                block,
+                weight: parent_weight + weight,
+                poh_hashes: parent_hashes + poh_hashes,
+            },
+        );
+        true
+    }
+
+    // Drop `block_hash` and everything built on it.
+    pub fn remove(&mut self, block_hash: &Hash) {
+        if let Some(node) = self.nodes.remove(block_hash) {
+            if let Some(siblings) = self.children.get_mut(&node.block.header.previous_hash) {
+                siblings.retain(|hash| hash != block_hash);
+            }
+        }
+        for child in self.children.remove(block_hash).unwrap_or_default() {
+            self.remove(&child);
+        }
+    }
+
+    // The preferred leaf under the fork-choice rule, or None if the tree holds
+    // no blocks and the root is the head.
+    pub fn head(&self) -> Option<Hash> {
+        self.nodes
+            .iter()
+            .filter(|(hash, _)| self.children.get(*hash).is_none_or(|children| children.is_empty()))
+            .max_by(|(a_hash, a), (b_hash, b)| self.compare(a, b).then_with(|| b_hash.cmp(a_hash)))
+            .map(|(hash, _)| *hash)
+    }
+
+    fn compare(&self, a: &TreeNode, b: &TreeNode) -> Ordering {
+        let by_weight = a.weight.total_cmp(&b.weight);
+        let by_poh = a.poh_hashes.cmp(&b.poh_hashes);
+        match self.fork_choice {
+            ForkChoice::HeaviestWeight => by_weight.then(by_poh),
+            ForkChoice::LongestPoh => by_poh.then(by_weight),
+        }
+    }
+
+    // The blocks from just after the root down to `block_hash`, oldest first.
+    pub fn chain_to(&self, block_hash: &Hash) -> Vec<&Block> {
+        let mut chain = Vec::new();
+        let mut current = *block_hash;
+        while let Some(node) = self.nodes.get(&current) {
+            chain.push(&node.block);
+            current = node.block.header.previous_hash;
+        }
+        chain.reverse();
+        chain
+    }
+
+    // Move the root up to `block_hash`, which must be in the tree, discarding
+    // every block that does not descend from it.
+    pub fn reroot(&mut self, block_hash: &Hash) {
+        let Some(node) = self.nodes.get(block_hash) else {
+            return;
+        };
+        let new_root = ChainTip::of(&node.block);
+        let (base_weight, base_hashes) = (node.weight, node.poh_hashes);
+
+        let mut kept = HashMap::new();
+        let mut pending = self.children.get(block_hash).cloned().unwrap_or_default();
+        while let Some(hash) = pending.pop() {
+            if let Some(mut node) = self.nodes.remove(&hash) {
+                node.weight -= base_weight;
+                node.poh_hashes -= base_hashes;
+                pending.extend(self.children.get(&hash).cloned().unwrap_or_default());
+                kept.insert(hash, node);
+            }
+        }
+
+        self.children.retain(|parent, _| *parent == new_root.block_hash || kept.contains_key(parent));
+        self.nodes = kept;
+        self.root = new_root;
+    }
+
+    // Start over from `root` with no blocks.
+    pub fn reset(&mut self, root: ChainTip) {
+        self.root = root;
+        self.nodes.clear();
+        self.children.clear();
+    }
+}
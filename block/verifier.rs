@@ -0,0 +1,384 @@
+use crate::block::block::{self, Block};
+use crate::crypto::keys::SignatureError;
+use crate::poh::hash::Hash;
+use crate::poh::entry::PohEntry;
+use crate::poh::verifier::{self, ParallelVerification, VerificationFailure};
+
+// Why a block was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockError {
+    HashMismatch { expected: Hash, found: Hash },
+    WrongShard { expected: usize, found: usize },
+    BrokenLink { expected: Hash, found: Hash },
+    UnexpectedNumber { expected: u64, found: u64 },
+    PohStartMismatch { expected: Hash, found: Hash },
+    PohEndMismatch { expected: Hash, found: Hash },
+    Poh(VerificationFailure),
+    TransactionsRootMismatch { expected: Hash, found: Hash },
+    TooManyTransactions { count: usize, max: usize },
+    TransactionsMismatch { index: usize }, // first position where bodies and entry ids disagree
+    InvalidSignature { index: usize, error: SignatureError },
+}
+
+// The point a chain continues from: the last block, or the anchor a snapshot
+// restored. The default tip is genesis.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChainTip {
+    pub block_number: u64,
+    pub block_hash: Hash,
+    pub poh_end_hash: Hash,
+}
+
+impl ChainTip {
+    pub fn of(block: &Block) -> Self {
+        ChainTip {
+            block_number: block.header.block_number,
+            block_hash: block.block_hash,
+            poh_end_hash: block.header.poh_end_hash,
+        }
+    }
+}
+
+// What the shard verifying a block requires of it beyond the chain rules.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockRules {
+    pub shard_id: usize,
+    pub max_transactions: usize,
+    pub max_hashes_per_entry: u64, // caps the hashing one entry can make the verifier do
+}
+
+impl Block {
+    // Check the block on its own: it belongs to the verifying shard, the hash
+    // covers the header, the entries are the ones the header commits to and
+    // every transaction is signed by its sender.
+    pub fn verify_contents(&self, rules: &BlockRules) -> Result<(), BlockError> {
+        self.verify_body(rules)?;
+        verifier::verify_entries(&self.header.poh_start_hash, &self.poh_entries).map_err(BlockError::Poh)
+    }
+
+    // Everything `verify_contents` checks short of recomputing the PoH entries.
+    fn verify_body(&self, rules: &BlockRules) -> Result<(), BlockError> {
+        let expected = self.header.hash();
+        if expected != self.block_hash {
+            return Err(BlockError::HashMismatch {
+                expected,
+                found: self.block_hash,
+            });
+        }
+        if self.header.shard_id != rules.shard_id {
+            return Err(BlockError::WrongShard {
+                expected: rules.shard_id,
+                found: self.header.shard_id,
+            });
+        }
+
+        let count = self.transaction_count();
+        if count > rules.max_transactions {
+            return Err(BlockError::TooManyTransactions {
+                count,
+                max: rules.max_transactions,
+            });
+        }
+
+        // the transaction bodies must be exactly the ones the entries recorded
+        let recorded_ids: Vec<&String> = self.poh_entries.iter().flat_map(|entry| &entry.transactions).collect();
+        let mismatch = (0..count.max(self.transactions.len()))
+            .find(|&index| recorded_ids.get(index).copied() != self.transactions.get(index).map(|tx| &tx.id));
+        if let Some(index) = mismatch {
+            return Err(BlockError::TransactionsMismatch { index });
+        }
+        for (index, tx) in self.transactions.iter().enumerate() {
+            tx.verify_signature()
+                .map_err(|error| BlockError::InvalidSignature { index, error })?;
+        }
+
+        let transactions_root = block::transactions_root(&self.transactions);
+        if transactions_root != self.header.transactions_root {
+            return Err(BlockError::TransactionsRootMismatch {
+                expected: transactions_root,
+                found: self.header.transactions_root,
+            });
+        }
+
+        verifier::check_hash_counts(&self.poh_entries, rules.max_hashes_per_entry).map_err(BlockError::Poh)?;
+
+        let poh_end = self
+            .poh_entries
+            .last()
+            .map(|entry| entry.hash)
+            .unwrap_or(self.header.poh_start_hash);
+        if poh_end != self.header.poh_end_hash {
+            return Err(BlockError::PohEndMismatch {
+                expected: poh_end,
+                found: self.header.poh_end_hash,
+            });
+        }
+        Ok(())
+    }
+
+    // Check that the block directly follows `tip`.
+    pub fn verify_link(&self, tip: &ChainTip) -> Result<(), BlockError> {
+        if self.header.block_number != tip.block_number + 1 {
+            return Err(BlockError::UnexpectedNumber {
+                expected: tip.block_number + 1,
+                found: self.header.block_number,
+            });
+        }
+        if self.header.previous_hash != tip.block_hash {
+            return Err(BlockError::BrokenLink {
+                expected: tip.block_hash,
+                found: self.header.previous_hash,
+            });
+        }
+        if self.header.poh_start_hash != tip.poh_end_hash {
+            return Err(BlockError::PohStartMismatch {
+                expected: tip.poh_end_hash,
+                found: self.header.poh_start_hash,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn verify(&self, tip: &ChainTip, rules: &BlockRules) -> Result<(), BlockError> {
+        self.verify_link(tip)?;
+        self.verify_contents(rules)
+    }
+}
+
+// Verify a run of blocks continuing from `base`, reporting the number of the
+// first block that fails. Each block's entries continue from the one before,
+// so together they are one PoH chain, which is recomputed on every core at
+// once rather than block by block.
+pub fn verify_chain(
+    base: &ChainTip,
+    blocks: &[Block],
+    rules: &BlockRules,
+) -> Result<ParallelVerification, (u64, BlockError)> {
+    let mut tip = *base;
+    let mut body_failure = None;
+    for (position, block) in blocks.iter().enumerate() {
+        if let Err(error) = block.verify_link(&tip).and_then(|()| block.verify_body(rules)) {
+            body_failure = Some((position, error));
+            break;
+        }
+        tip = ChainTip::of(block);
+    }
+
+    let checked = &blocks[..body_failure.as_ref().map_or(blocks.len(), |(position, _)| *position)];
+    let entries: Vec<PohEntry> = checked.iter().flat_map(|block| block.poh_entries.iter().cloned()).collect();
+    let verification = verifier::verify_entries_parallel(&base.poh_end_hash, &entries, 0);
+    if let Err(failure) = &verification.result {
+        // an earlier block failing its entries comes before any body failure
+        let mut index = failure.index;
+        for block in checked {
+            if index < block.poh_entries.len() {
+                let fault = failure.fault.clone();
+                return Err((block.header.block_number, BlockError::Poh(VerificationFailure { index, fault })));
+            }
+            index -= block.poh_entries.len();
+        }
+    }
+
+    match body_failure {
+        Some((position, error)) => Err((blocks[position].header.block_number, error)),
+        None => Ok(verification),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::block::BlockHeader;
+    use crate::crypto::keys;
+    use crate::poh::clock;
+    use crate::poh::config::PohConfig;
+    use crate::poh::generator::PohGenerator;
+    use crate::poh::verifier::EntryFault;
+    use crate::shard::shard::{Transaction, TransactionStatus};
+
+    fn config() -> PohConfig {
+        PohConfig {
+            hashes_per_tick: 4,
+            ticks_per_slot: 2,
+            ..PohConfig::default()
+        }
+    }
+
+    fn rules() -> BlockRules {
+        BlockRules {
+            shard_id: 1,
+            max_transactions: 10,
+            max_hashes_per_entry: config().max_hashes_per_entry(),
+        }
+    }
+
+    // `count` blocks of two ticks each on shard 1, continuing from genesis.
+    fn chain(count: u64) -> Vec<Block> {
+        let mut generator = PohGenerator::with_config(10, config(), clock::system_clock());
+        let mut tip = ChainTip::default();
+        let mut blocks = Vec::new();
+        for number in 1..=count {
+            let entries = vec![generator.tick(), generator.tick()];
+            let header = BlockHeader::new(number, tip.block_hash, 1, 0, 0, Hash::default(), 1_700_000_000);
+            let block = Block::new(header, tip.poh_end_hash, entries, Vec::new());
+            tip = ChainTip::of(&block);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn chain_verifies_from_genesis() {
+        let verification = verify_chain(&ChainTip::default(), &chain(4), &rules()).unwrap();
+        assert_eq!(verification.entries_verified, 8);
+        assert_eq!(verification.hashes_verified, 8 * 4);
+    }
+
+    #[test]
+    fn chain_reports_the_first_bad_block_and_why() {
+        let mut blocks = chain(4);
+        blocks[2].poh_entries[1].num_hashes += 1;
+        blocks[3].poh_entries[0].num_hashes += 1;
+        let (block_number, error) = verify_chain(&ChainTip::default(), &blocks, &rules()).unwrap_err();
+        assert_eq!(block_number, 3);
+        assert!(matches!(
+            error,
+            BlockError::Poh(VerificationFailure {
+                index: 1,
+                fault: EntryFault::HashMismatch { .. }
+            })
+        ));
+
+        let mut blocks = chain(4);
+        let forged = Hash::digest(b"forged");
+        blocks[1].header.previous_hash = forged;
+        assert_eq!(
+            verify_chain(&ChainTip::default(), &blocks, &rules()).unwrap_err(),
+            (
+                2,
+                BlockError::BrokenLink {
+                    expected: blocks[0].block_hash,
+                    found: forged
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn bad_entries_before_a_bad_body_are_reported_first() {
+        let mut blocks = chain(4);
+        blocks[0].poh_entries[1].num_hashes += 1;
+        blocks[2].header.shard_id = 2;
+        let (block_number, error) = verify_chain(&ChainTip::default(), &blocks, &rules()).unwrap_err();
+        assert_eq!(block_number, 1);
+        assert!(matches!(error, BlockError::Poh(VerificationFailure { index: 1, .. })));
+    }
+
+    fn transfer(nonce: u64) -> Transaction {
+        let keypair = keys::test_keypair("alice");
+        let mut tx = Transaction {
+            id: format!("tx-{}", nonce),
+            from_account: keypair.address(),
+            to_account: "bob".to_string(),
+            amount: 10,
+            fee: 1,
+            nonce,
+            from_shard: 1,
+            to_shard: 1,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Pending,
+        };
+        tx.sign(&keypair);
+        tx
+    }
+
+    // Block #1 on shard 1 recording three transfers in one entry.
+    fn block() -> Block {
+        let mut generator = PohGenerator::with_config(10, config(), clock::system_clock());
+        let transactions: Vec<Transaction> = (0..3).map(transfer).collect();
+        let ids = transactions.iter().map(|tx| tx.id.clone()).collect();
+        let entries = vec![generator.tick(), generator.record(ids, Hash::default())];
+        let header = BlockHeader::new(1, Hash::default(), 1, 0, 0, Hash::default(), 1_700_000_000);
+        Block::new(header, Hash::default(), entries, transactions)
+    }
+
+    // Re-seal the header after tampering with it, as a forger would.
+    fn resealed(mut block: Block) -> Block {
+        block.block_hash = block.header.hash();
+        block
+    }
+
+    fn verify(block: &Block) -> Result<(), BlockError> {
+        block.verify(&ChainTip::default(), &rules())
+    }
+
+    #[test]
+    fn block_verifies_against_its_parent() {
+        assert_eq!(verify(&block()), Ok(()));
+    }
+
+    #[test]
+    fn header_and_link_errors() {
+        let mut unsealed = block();
+        unsealed.header.timestamp += 1;
+        assert!(matches!(verify(&unsealed), Err(BlockError::HashMismatch { .. })));
+
+        let mut foreign = block();
+        foreign.header.shard_id = 2;
+        assert_eq!(verify(&resealed(foreign)), Err(BlockError::WrongShard { expected: 1, found: 2 }));
+
+        let mut skipped = block();
+        skipped.header.block_number = 2;
+        assert_eq!(verify(&resealed(skipped)), Err(BlockError::UnexpectedNumber { expected: 1, found: 2 }));
+
+        let mut detached = block();
+        detached.header.poh_start_hash = Hash::digest(b"elsewhere");
+        assert!(matches!(verify(&resealed(detached)), Err(BlockError::PohStartMismatch { .. })));
+
+        let mut truncated = block();
+        truncated.header.poh_end_hash = truncated.poh_entries[0].hash;
+        assert!(matches!(verify(&resealed(truncated)), Err(BlockError::PohEndMismatch { .. })));
+    }
+
+    #[test]
+    fn body_errors() {
+        let small = BlockRules { max_transactions: 2, ..rules() };
+        assert_eq!(
+            block().verify(&ChainTip::default(), &small),
+            Err(BlockError::TooManyTransactions { count: 3, max: 2 })
+        );
+
+        let mut swapped = block();
+        swapped.transactions.swap(1, 2);
+        assert_eq!(verify(&swapped), Err(BlockError::TransactionsMismatch { index: 1 }));
+
+        let mut missing = block();
+        missing.transactions.pop();
+        assert_eq!(verify(&missing), Err(BlockError::TransactionsMismatch { index: 2 }));
+
+        let mut forged = block();
+        forged.transactions[1].amount += 1;
+        assert!(matches!(
+            verify(&forged),
+            Err(BlockError::InvalidSignature { index: 1, .. })
+        ));
+
+        let mut rerooted = block();
+        rerooted.header.transactions_root = Hash::digest(b"other");
+        assert!(matches!(
+            verify(&resealed(rerooted)),
+            Err(BlockError::TransactionsRootMismatch { .. })
+        ));
+
+        let mut rehashed = block();
+        rehashed.poh_entries[1].num_hashes += 1;
+        assert!(matches!(
+            verify(&rehashed),
+            Err(BlockError::Poh(VerificationFailure {
+                index: 1,
+                fault: EntryFault::HashMismatch { .. }
+            }))
+        ));
+    }
+}
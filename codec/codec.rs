@@ -0,0 +1,283 @@
+use crate::poh::hash::{Hash, HASH_BYTES};
+use std::fmt;
+
+// Bumped whenever the byte layout of any encoded type changes. Every top-level
+// encoding starts with this byte, so hashes from different layouts never collide.
+pub const ENCODING_VERSION: u8 = 2;
+
+// Canonical binary encoding used both for hashing and on the wire. Integers
+// are fixed-width little-endian, variable-length data carries a u64 length
+// prefix, and collections are written in a deterministic order.
+pub trait Encode {
+    fn encode(&self, encoder: &mut Encoder);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::versioned();
+        self.encode(&mut encoder);
+        encoder.finish()
+    }
+
+    fn canonical_hash(&self) -> Hash {
+        Hash::digest(&self.to_bytes())
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        let version = decoder.u8()?;
+        if version != ENCODING_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let value = Self::decode(&mut decoder)?;
+        decoder.finish()?;
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    InvalidUtf8,
+    InvalidTag(u8),
+    TrailingBytes(usize),
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::UnsupportedVersion(version) => write!(f, "unsupported encoding version {}", version),
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid enum tag {}", tag),
+            DecodeError::TrailingBytes(count) => write!(f, "{} trailing bytes after value", count),
+            DecodeError::InvalidValue(what) => write!(f, "invalid value: {}", what),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder { buf: Vec::new() }
+    }
+
+    // An encoder that has already written the version byte, for hashing ad-hoc
+    // field lists under the same versioning as whole values.
+    pub fn versioned() -> Self {
+        let mut encoder = Encoder::new();
+        encoder.put_u8(ENCODING_VERSION);
+        encoder
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // usize is always written as 8 bytes so the encoding is the same on every platform.
+    pub fn put_usize(&mut self, value: usize) {
+        self.put_u64(value as u64);
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_usize(bytes.len());
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn put_str(&mut self, value: &str) {
+        self.put_bytes(value.as_bytes());
+    }
+
+    // Fixed-size values are written without a length.
+    pub fn put_fixed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn put_hash(&mut self, hash: &Hash) {
+        self.buf.extend_from_slice(hash.as_ref());
+    }
+
+    pub fn put_seq<'a, T: Encode + 'a>(&mut self, items: impl ExactSizeIterator<Item = &'a T>) {
+        self.put_usize(items.len());
+        for item in items {
+            item.encode(self);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Decoder { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, DecodeError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, DecodeError> {
+        usize::try_from(self.u64()?).map_err(|_| DecodeError::InvalidValue("length does not fit in usize"))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    pub fn fixed<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn hash(&mut self) -> Result<Hash, DecodeError> {
+        Ok(Hash(self.take(HASH_BYTES)?.try_into().unwrap()))
+    }
+
+    pub fn seq<T: Decode>(&mut self) -> Result<Vec<T>, DecodeError> {
+        let len = self.usize()?;
+        // every element takes at least one byte, so a longer count is bogus
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        (0..len).map(|_| T::decode(self)).collect()
+    }
+
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            trailing => Err(DecodeError::TrailingBytes(trailing)),
+        }
+    }
+}
+
+impl Encode for Hash {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_hash(self);
+    }
+}
+
+impl Decode for Hash {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        decoder.hash()
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_str(self);
+    }
+}
+
+impl Decode for String {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        decoder.string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let hash = Hash::digest(b"payload");
+        assert_eq!(Hash::from_bytes(&hash.to_bytes()), Ok(hash));
+
+        let text = "héllo".to_string();
+        assert_eq!(String::from_bytes(&text.to_bytes()), Ok(text));
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let mut encoder = Encoder::new();
+        encoder.put_u8(7);
+        encoder.put_u64(u64::MAX);
+        encoder.put_i64(-42);
+        encoder.put_usize(3);
+        encoder.put_bytes(b"abc");
+        encoder.put_fixed(&[1, 2, 3, 4]);
+        let bytes = encoder.finish();
+
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.u8(), Ok(7));
+        assert_eq!(decoder.u64(), Ok(u64::MAX));
+        assert_eq!(decoder.i64(), Ok(-42));
+        assert_eq!(decoder.usize(), Ok(3));
+        assert_eq!(decoder.bytes(), Ok(&b"abc"[..]));
+        assert_eq!(decoder.fixed::<4>(), Ok([1, 2, 3, 4]));
+        assert_eq!(decoder.finish(), Ok(()));
+    }
+
+    #[test]
+    fn refuses_malformed_input() {
+        let mut bytes = "abc".to_string().to_bytes();
+        bytes.push(0);
+        assert_eq!(String::from_bytes(&bytes), Err(DecodeError::TrailingBytes(1)));
+
+        let mut bytes = "abc".to_string().to_bytes();
+        bytes[0] = ENCODING_VERSION + 1;
+        assert_eq!(
+            String::from_bytes(&bytes),
+            Err(DecodeError::UnsupportedVersion(ENCODING_VERSION + 1))
+        );
+
+        let bytes = "abc".to_string().to_bytes();
+        assert_eq!(String::from_bytes(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEnd));
+
+        // a sequence claiming more elements than there are bytes left
+        let mut encoder = Encoder::new();
+        encoder.put_usize(1_000);
+        let bytes = encoder.finish();
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.seq::<Hash>(), Err(DecodeError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::codec::codec::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::poh::hash::{self, Hash};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use std::fmt;
+
+pub const PUBLIC_KEY_BYTES: usize = 32;
+pub const SIGNATURE_BYTES: usize = 64;
+pub const ADDRESS_BYTES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    InvalidPublicKey,
+    BadSignature,
+    AddressMismatch { address: String, expected: String }, // the key does not own the sending account
+}
+
+// An account address: the first `ADDRESS_BYTES` of the SHA-256 of the
+// account's public key, in hex.
+pub fn address_of(public_key: &PublicKey) -> String {
+    hash::to_hex(&Hash::digest(&public_key.0).as_bytes()[..ADDRESS_BYTES])
+}
+
+// Ed25519 keypair. Debug output shows only the public half.
+#[derive(Clone)]
+pub struct Keypair {
+    signing_key: SigningKey,
+}
+
+impl Keypair {
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Keypair {
+            signing_key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn address(&self) -> String {
+        address_of(&self.public_key())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature(self.signing_key.sign(message).to_bytes())
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Keypair({})", self.address())
+    }
+}
+
+// The same keypair for the same label on every run and every node, for
+// simulations and tests. Never use these for real funds.
+pub fn test_keypair(label: &str) -> Keypair {
+    let seed = Hash::hashv(&[b"pohts test keypair", label.as_bytes()]);
+    Keypair::from_seed(*seed.as_bytes())
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct PublicKey(pub [u8; PUBLIC_KEY_BYTES]);
+
+impl PublicKey {
+    // Strict verification refuses small-order keys and non-canonical
+    // signatures, so a valid signature cannot be altered into another valid
+    // one for the same transaction.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        let key = VerifyingKey::from_bytes(&self.0).map_err(|_| SignatureError::InvalidPublicKey)?;
+        let signature = ed25519_dalek::Signature::from_bytes(&signature.0);
+        key.verify_strict(message, &signature).map_err(|_| SignatureError::BadSignature)
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey(")?;
+        hash::write_hex(f, &self.0)?;
+        write!(f, ")")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; SIGNATURE_BYTES]);
+
+// All zeroes: the placeholder on a transaction that has not been signed.
+impl Default for Signature {
+    fn default() -> Self {
+        Signature([0; SIGNATURE_BYTES])
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signature(")?;
+        hash::write_hex(f, &self.0)?;
+        write!(f, ")")
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_fixed(&self.0);
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(PublicKey(decoder.fixed()?))
+    }
+}
+
+impl Encode for Signature {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_fixed(&self.0);
+    }
+}
+
+impl Decode for Signature {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Signature(decoder.fixed()?))
+    }
+}
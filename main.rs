@@ -1,14 +1,31 @@
 mod poh;
 mod block;
+mod codec;
+mod crypto;
+mod storage;
 mod shard;
 mod network;
 mod validator;
 
 use actix_files::NamedFile;
-use actix_web::{get, web, App, HttpServer, Responder, HttpResponse};
-use serde::Serialize;
+use actix_web::{get, post, web, App, HttpServer, Responder, HttpResponse};
+use serde::{Deserialize, Serialize};
 use shard::shard::{Shard, Transaction, TransactionStatus, Checkpoint};
 use crate::validator::validator::Validator;
+use crate::poh::clock;
+use crate::poh::elapsed::{ElapsedProof, ElapsedProofLimits};
+use crate::poh::entry::PohEntry;
+use crate::poh::merkle::MerkleProof;
+use crate::shard::ledger::BalanceProof;
+use crate::shard::mempool::MempoolConfig;
+use crate::shard::tx_index::TransactionLocation;
+use crate::shard::validation::{AmountBounds, DEFAULT_MIN_AMOUNT};
+use crate::block::block::{Block, InclusionProof};
+use crate::block::tree::ForkChoice;
+use crate::codec::codec::Decode;
+use crate::crypto::keys::{self, Keypair};
+use crate::storage::snapshot_store::DEFAULT_SNAPSHOT_RETENTION;
+use crate::poh::config::{PohConfig, DEFAULT_TARGET_SLOT_MS, DEFAULT_TICKS_PER_SLOT};
 use network::gossip_protocol::GossipProtocol;
 use network::bootstrap::bootstrap_node::BootstrapNode;
 use std::sync::{Arc, Mutex};
@@ -20,7 +37,7 @@ use std::fs::OpenOptions;
 use std::io::{Write, Read};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::net::TcpStream;
 
@@ -28,8 +45,24 @@ use std::net::TcpStream;
 extern crate lazy_static;
 
 const MAX_TRANSACTIONS_PER_BLOCK: usize = 3000;
+// A shard's pool holds a few full blocks' worth of transfers, and as many credits.
+const MEMPOOL_MAX_TRANSACTIONS: usize = MAX_TRANSACTIONS_PER_BLOCK * 4;
+const MEMPOOL_MAX_CREDITS: usize = MAX_TRANSACTIONS_PER_BLOCK * 4;
 const BOOTSTRAP_TCP_PORT: u16 = 8081;
 const WEB_SERVER_PORT: u16 = 8090;
+const POH_CONFIG_PATH: &str = "poh_config.json";
+const STORAGE_DIR: &str = "data";
+const ACCOUNTS_PER_SHARD: usize = 10;
+const GENESIS_BALANCE: u64 = 1_000_000;
+// No single transfer may move more than a tenth of a genesis allocation.
+const MAX_TRANSFER_AMOUNT: u64 = GENESIS_BALANCE / 10;
+// The random load pays the minimum fee, so anything paying more per byte
+// goes into blocks ahead of it.
+const LOAD_GENERATOR_FEE: u64 = 1;
+const SHARD_COUNT: usize = 10;
+// Every shard runs PoH on its own thread. Between them they get this share of
+// the machine, leaving the rest for verifying blocks and serving requests.
+const POH_CPU_SHARE: f64 = 0.5;
 
 #[derive(Serialize, Clone)]
 struct ShardInfo {
@@ -68,6 +101,8 @@ struct ShardStats {
     validators: Vec<ValidatorStats>,
     checkpoint: Option<CheckpointDetail>,
     block_count: usize,
+    transaction_pool_bytes: usize,
+    pending_credits: usize, // credits for transfers debited on other shards, never evicted
 }
 
 #[derive(Serialize)]
@@ -101,6 +136,110 @@ struct CheckpointDetail {
     processed_transaction_count: usize,
 }
 
+#[derive(Deserialize)]
+struct ElapsedQuery {
+    shard_id: usize,
+    first_tx: String,
+    second_tx: String,
+}
+
+#[derive(Serialize)]
+struct ElapsedVerification {
+    valid: bool,
+    num_hashes: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BalanceQuery {
+    shard_id: usize,
+    account: String,
+}
+
+// A balance proof along with the block whose state root it proves against.
+#[derive(Serialize)]
+struct BalanceResponse {
+    block_number: u64,
+    state_root: poh::hash::Hash,
+    proof: BalanceProof,
+}
+
+#[derive(Deserialize)]
+struct BalanceVerificationRequest {
+    state_root: poh::hash::Hash,
+    proof: BalanceProof,
+}
+
+#[derive(Serialize)]
+struct BalanceVerification {
+    valid: bool,
+}
+
+#[derive(Deserialize)]
+struct ReceiptQuery {
+    shard_id: usize,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct ReceiptResponse {
+    id: String,
+    shard_id: usize,
+    status: String,
+    location: Option<TransactionLocation>,
+    proof: Option<InclusionProof>,
+    entry_proof: Option<MerkleProof>,
+}
+
+// `entry` and `entry_proof` go together: with them, the transaction must also
+// be proven in that entry. Only the entry's transaction root is used.
+#[derive(Deserialize)]
+struct ReceiptVerificationRequest {
+    block_hash: poh::hash::Hash,
+    proof: InclusionProof,
+    entry: Option<PohEntry>,
+    entry_proof: Option<MerkleProof>,
+}
+
+#[derive(Serialize)]
+struct ReceiptVerification {
+    valid: bool,
+}
+
+#[derive(Deserialize)]
+struct TransactionQuery {
+    shard_id: usize,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct TransactionResponse {
+    id: String,
+    from_account: String,
+    to_account: String,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+    from_shard: usize,
+    to_shard: usize,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct TransactionStatusQuery {
+    shard_id: usize,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct StatusChangeDetail {
+    status: String,
+    reason: String,
+    at: String,
+    block_number: Option<u64>,
+    block_hash: Option<poh::hash::Hash>,
+}
+
 lazy_static! {
     static ref BLOCK_GEN_TIMES: Mutex<Vec<Duration>> = Mutex::new(Vec::new());
     static ref LAST_BLOCK_TIMESTAMP: Mutex<Option<chrono::DateTime<Utc>>> = Mutex::new(None);
@@ -109,44 +248,37 @@ lazy_static! {
 struct AppState {
     shards: Arc<Mutex<Vec<Shard>>>,
     transaction_start_times: Arc<Mutex<HashMap<String, Instant>>>,
-    block_gen_times: Arc<Mutex<Vec<Duration>>>,
     shard_info: Vec<ShardInfo>,
     nodes: Arc<Mutex<Vec<String>>>, 
     start_time: Instant,
     transaction_batch_size: Arc<Mutex<usize>>,
     delay_in_ms: Arc<Mutex<u64>>,
+    elapsed_proof_limits: ElapsedProofLimits,
 }
 
 #[get("/api/stats")]
 async fn get_stats(data: web::Data<AppState>) -> impl Responder {
     let shards = data.shards.lock().unwrap();
     let tx_start_times = data.transaction_start_times.lock().unwrap();
-    let block_gen_times = data.block_gen_times.lock().unwrap();
 
-    let total_blocks: usize = shards.iter().map(|shard| shard.blocks.len()).sum();
+    let total_blocks: usize = shards.iter().map(|shard| shard.tip().block_number as usize).sum();
     let total_transactions: usize = shards.iter().map(|shard| shard.get_processed_transaction_count()).sum();
 
+    // sizes are averaged over the blocks still held in memory
+    let recent_blocks: usize = shards.iter().map(|shard| shard.blocks.len()).sum();
     let total_block_size: usize = shards.iter()
         .flat_map(|shard| shard.blocks.iter())
-        .map(|block| std::mem::size_of_val(block))
+        .map(std::mem::size_of_val)
         .sum();
 
-    let avg_block_size = if total_blocks > 0 {
-        total_block_size / total_blocks
-    } else {
-        0
-    };
+    let avg_block_size = total_block_size.checked_div(recent_blocks).unwrap_or(0);
 
-    let total_tx_size: usize = shards.iter()
+    let recent_transactions: Vec<&Transaction> = shards.iter()
         .flat_map(|shard| shard.get_processed_transactions())
-        .map(|tx| std::mem::size_of_val(tx))
-        .sum();
+        .collect();
+    let total_tx_size: usize = recent_transactions.iter().map(|tx| std::mem::size_of_val(*tx)).sum();
 
-    let avg_tx_size = if total_transactions > 0 {
-        total_tx_size / total_transactions
-    } else {
-        0
-    };
+    let avg_tx_size = total_tx_size.checked_div(recent_transactions.len()).unwrap_or(0);
 
     let transaction_pool_size: usize = shards.iter().map(|shard| shard.get_transaction_pool().len()).sum();
     let total_cross_shard_transactions: usize = shards.iter().map(|shard| shard.get_pending_cross_shard_txs_len()).sum();
@@ -162,45 +294,39 @@ async fn get_stats(data: web::Data<AppState>) -> impl Responder {
         let mut validators = Vec::new();
 
         for block in &shard.blocks {
-            for tx in &block.poh_entries[0].transactions {
-                if let Some(transaction) = shard.get_transaction_by_id(tx) {
-                    let status = if transaction.status == TransactionStatus::Completed {
-                        String::from("Completed")
-                    } else {
-                        String::from("Pending")
-                    };
-
-                    let block_number = block.block_number.to_string();
-                    let shard_number = shard.id;
-                    let processing_time_ms = tx_start_times.get(&transaction.id).map(|start_time| start_time.elapsed().as_millis());
-
-                    transactions.push(TransactionDetail {
-                        id: transaction.id.clone(),
-                        status,
-                        processing_time_ms,
-                        block_number,
-                        shard_number,
-                    });
-
-                    if transaction.status == TransactionStatus::Completed {
-                        if let Some(duration) = processing_time_ms {
-                            total_confirmation_time += duration;
-                            confirmed_tx_count += 1;
-                        }
-                    }
+            for transaction in &block.transactions {
+                let status = shard.current_status(&transaction.id).unwrap_or(TransactionStatus::Completed);
+
+                let block_number = block.header.block_number.to_string();
+                let shard_number = shard.id;
+                let processing_time_ms = tx_start_times.get(&transaction.id).map(|start_time| start_time.elapsed().as_millis());
+
+                transactions.push(TransactionDetail {
+                    id: transaction.id.clone(),
+                    status: format!("{:?}", status),
+                    processing_time_ms,
+                    block_number,
+                    shard_number,
+                });
 
-                    if transaction.from_shard != transaction.to_shard {
-                        cross_shard_tx_count += 1;
-                        total_cross_shard_processing_time += tx_start_times
-                            .get(&transaction.id)
-                            .map(|start_time| start_time.elapsed().as_secs_f64())
-                            .unwrap_or(0.0);
+                if status == TransactionStatus::Completed {
+                    if let Some(duration) = processing_time_ms {
+                        total_confirmation_time += duration;
+                        confirmed_tx_count += 1;
                     }
                 }
+
+                if transaction.from_shard != transaction.to_shard {
+                    cross_shard_tx_count += 1;
+                    total_cross_shard_processing_time += tx_start_times
+                        .get(&transaction.id)
+                        .map(|start_time| start_time.elapsed().as_secs_f64())
+                        .unwrap_or(0.0);
+                }
             }
         }
 
-        for tx in shard.get_transaction_pool() {
+        for tx in shard.get_transaction_pool().iter() {
             let tx_id = &tx.id;
             let status = format!("{:?}", tx.status);
             let shard_number = shard.id;
@@ -232,6 +358,16 @@ async fn get_stats(data: web::Data<AppState>) -> impl Responder {
             });
         }
 
+        for tx in shard.get_failed_transactions().iter() {
+            transactions.push(TransactionDetail {
+                id: tx.id.clone(),
+                status: format!("{:?}", tx.status),
+                processing_time_ms: None,
+                block_number: "Rejected".to_string(),
+                shard_number: shard.id,
+            });
+        }
+
         for validator in shard.get_validators() {
             validators.push(ValidatorStats {
                 id: validator.id,
@@ -246,7 +382,7 @@ async fn get_stats(data: web::Data<AppState>) -> impl Responder {
             });
         }
 
-        let checkpoint_detail = shard.pending_checkpoint.as_ref().map(|cp| CheckpointDetail {
+        let checkpoint_detail = shard.last_checkpoint.as_ref().map(|cp| CheckpointDetail {
             shard_id: cp.shard_id,
             block_height: cp.block_height,
             transaction_pool_size: cp.transaction_pool_snapshot.len(),
@@ -259,7 +395,9 @@ async fn get_stats(data: web::Data<AppState>) -> impl Responder {
             transactions,
             validators,
             checkpoint: checkpoint_detail,
-            block_count: shard.blocks.len(),
+            block_count: shard.tip().block_number as usize,
+            transaction_pool_bytes: shard.get_transaction_pool().bytes(),
+            pending_credits: shard.get_transaction_pool().credit_count(),
         });
     }
 
@@ -348,6 +486,170 @@ async fn get_nodes(data: web::Data<AppState>) -> impl Responder {
     HttpResponse::Ok().json(nodes_list)
 }
 
+#[get("/api/poh/elapsed")]
+async fn get_elapsed_proof(data: web::Data<AppState>, query: web::Query<ElapsedQuery>) -> impl Responder {
+    let shards = data.shards.lock().unwrap();
+    let shard = match shards.iter().find(|shard| shard.id == query.shard_id) {
+        Some(shard) => shard,
+        None => return HttpResponse::NotFound().body("Unknown shard"),
+    };
+
+    match shard.prove_elapsed(&query.first_tx, &query.second_tx) {
+        Ok(proof) => HttpResponse::Ok().json(proof),
+        Err(e) => HttpResponse::NotFound().body(e),
+    }
+}
+
+// Verifying means recomputing every hash in the proof, so it runs on the
+// blocking pool rather than on a server worker.
+#[post("/api/poh/elapsed/verify")]
+async fn verify_elapsed_proof(data: web::Data<AppState>, proof: web::Json<ElapsedProof>) -> impl Responder {
+    let limits = data.elapsed_proof_limits;
+    let result = match web::block(move || proof.verify(&limits)).await {
+        Ok(result) => result,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let verification = match result {
+        Ok(num_hashes) => ElapsedVerification {
+            valid: true,
+            num_hashes: Some(num_hashes),
+            error: None,
+        },
+        Err(e) => ElapsedVerification {
+            valid: false,
+            num_hashes: None,
+            error: Some(format!("{:?}", e)),
+        },
+    };
+    HttpResponse::Ok().json(verification)
+}
+
+#[get("/api/state/balance")]
+async fn get_balance_proof(data: web::Data<AppState>, query: web::Query<BalanceQuery>) -> impl Responder {
+    let shards = data.shards.lock().unwrap();
+    let shard = match shards.iter().find(|shard| shard.id == query.shard_id) {
+        Some(shard) => shard,
+        None => return HttpResponse::NotFound().body("Unknown shard"),
+    };
+
+    HttpResponse::Ok().json(BalanceResponse {
+        block_number: shard.tip().block_number,
+        state_root: shard.state_root(),
+        proof: shard.prove_balance(&query.account),
+    })
+}
+
+#[post("/api/state/balance/verify")]
+async fn verify_balance_proof(request: web::Json<BalanceVerificationRequest>) -> impl Responder {
+    HttpResponse::Ok().json(BalanceVerification {
+        valid: request.proof.verify(&request.state_root),
+    })
+}
+
+// Every status the shard has given a transaction, oldest first, with why.
+#[get("/api/transaction/status")]
+async fn get_transaction_status(data: web::Data<AppState>, query: web::Query<TransactionStatusQuery>) -> impl Responder {
+    let shards = data.shards.lock().unwrap();
+    let shard = match shards.iter().find(|shard| shard.id == query.shard_id) {
+        Some(shard) => shard,
+        None => return HttpResponse::NotFound().body("Unknown shard"),
+    };
+    let history = match shard.get_status_index().history(&query.id) {
+        Some(history) => history,
+        None => return HttpResponse::NotFound().body("Unknown transaction"),
+    };
+
+    let changes: Vec<StatusChangeDetail> = history
+        .iter()
+        .map(|change| StatusChangeDetail {
+            status: format!("{:?}", change.status),
+            reason: format!("{:?}", change.reason),
+            at: change.at.to_rfc3339(),
+            block_number: change.block.map(|block| block.block_number),
+            block_hash: change.block.and_then(|block| block.block_hash),
+        })
+        .collect();
+    HttpResponse::Ok().json(changes)
+}
+
+// The transaction itself, wherever the shard still holds it: in the pool, on
+// the chain or among the recent failures.
+#[get("/api/transaction")]
+async fn get_transaction(data: web::Data<AppState>, query: web::Query<TransactionQuery>) -> impl Responder {
+    let shards = data.shards.lock().unwrap();
+    let shard = match shards.iter().find(|shard| shard.id == query.shard_id) {
+        Some(shard) => shard,
+        None => return HttpResponse::NotFound().body("Unknown shard"),
+    };
+
+    match shard.get_transaction_by_id(&query.id) {
+        Some(tx) => HttpResponse::Ok().json(TransactionResponse {
+            id: tx.id,
+            from_account: tx.from_account,
+            to_account: tx.to_account,
+            amount: tx.amount,
+            fee: tx.fee,
+            nonce: tx.nonce,
+            from_shard: tx.from_shard,
+            to_shard: tx.to_shard,
+            status: format!("{:?}", tx.status),
+        }),
+        None => HttpResponse::NotFound().body("Unknown transaction"),
+    }
+}
+
+#[get("/api/transaction/receipt")]
+async fn get_transaction_receipt(data: web::Data<AppState>, query: web::Query<ReceiptQuery>) -> impl Responder {
+    let shards = data.shards.lock().unwrap();
+    let shard = match shards.iter().find(|shard| shard.id == query.shard_id) {
+        Some(shard) => shard,
+        None => return HttpResponse::NotFound().body("Unknown shard"),
+    };
+
+    match shard.transaction_receipt(&query.id) {
+        Some(receipt) => HttpResponse::Ok().json(ReceiptResponse {
+            id: receipt.tx_id,
+            shard_id: receipt.shard_id,
+            status: format!("{:?}", receipt.status),
+            location: receipt.location,
+            proof: receipt.proof,
+            entry_proof: receipt.entry_proof,
+        }),
+        None => HttpResponse::NotFound().body("Unknown transaction"),
+    }
+}
+
+#[post("/api/transaction/receipt/verify")]
+async fn verify_transaction_receipt(request: web::Json<ReceiptVerificationRequest>) -> impl Responder {
+    let in_entry = match (&request.entry, &request.entry_proof) {
+        (Some(entry), Some(entry_proof)) => entry.verify_transaction_proof(&request.proof.tx_id, entry_proof),
+        (None, None) => true,
+        _ => false,
+    };
+    HttpResponse::Ok().json(ReceiptVerification {
+        valid: request.proof.verify(&request.block_hash) && in_entry,
+    })
+}
+
+// A block of one of this node's shards built by a peer, as its canonical
+// bytes. It is appended like any block, possibly on a competing branch.
+#[post("/api/block")]
+async fn receive_block(data: web::Data<AppState>, body: web::Bytes) -> impl Responder {
+    let block = match Block::from_bytes(&body) {
+        Ok(block) => block,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let mut shards = data.shards.lock().unwrap();
+    let shard = match shards.iter_mut().find(|shard| shard.id == block.header.shard_id) {
+        Some(shard) => shard,
+        None => return HttpResponse::NotFound().body("Unknown shard"),
+    };
+    match shard.receive_block(block) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => HttpResponse::UnprocessableEntity().body(e.to_string()),
+    }
+}
+
 async fn index() -> impl Responder {
     NamedFile::open("./static/index.html").unwrap()
 }
@@ -356,37 +658,62 @@ fn send_random_transactions(
     shards: Arc<Mutex<Vec<Shard>>>,
     gossip_protocol: Arc<Mutex<GossipProtocol>>,
     tx_start_times: Arc<Mutex<HashMap<String, Instant>>>,
-    block_gen_times: Arc<Mutex<Vec<Duration>>>,
     transaction_batch_size: Arc<Mutex<usize>>,
     delay_in_ms: Arc<Mutex<u64>>,
 ) {
     thread::spawn(move || {
         let mut rng = rand::thread_rng();
         let mut tx_count = 1;
-        let mut log_file = OpenOptions::new()
+        // ids carry the start time, so a restarted node never reuses one from an earlier run
+        let run_id = Utc::now().timestamp_millis();
+        // transactions a full pool turned away, offered again before new ones
+        let mut backlog: VecDeque<Transaction> = VecDeque::new();
+        let _log_file = OpenOptions::new()
             .create(true)
             .append(true)
             .open("blockchain_metrics.log")
             .expect("Cannot open log file");
 
         loop {
-            let batch_size = rng.gen_range(1..2);
+            while let Some(transaction) = backlog.pop_front() {
+                let mut shards = shards.lock().unwrap();
+                let shard_index = transaction.from_shard - 1;
+                let result = shards[shard_index].process_transactions(vec![transaction.clone()]).remove(0);
+                if matches!(result, Err(ref e) if e.is_retryable()) {
+                    backlog.push_front(transaction);
+                    break;
+                }
+            }
+
+            // hold off on new transactions until the backlog has drained
+            let batch_size = if backlog.is_empty() { rng.gen_range(1..2) } else { 0 };
             *transaction_batch_size.lock().unwrap() = batch_size;
 
             for _ in 0..batch_size {
                 let amount = rng.gen_range(1..1000);
                 let shard_index = rng.gen_range(0..shards.lock().unwrap().len());
 
-                let transaction_id = format!("tx{}", tx_count);
+                let transaction_id = format!("tx{}-{}", run_id, tx_count);
                 let to_shard = hash_to_shard(&transaction_id, shards.lock().unwrap().len());
 
-                let transaction = Transaction {
+                let sender = account_keypair(shard_index + 1, rng.gen_range(0..ACCOUNTS_PER_SHARD));
+                let from_account = sender.address();
+                let nonce = shards.lock().unwrap()[shard_index].next_nonce(&from_account);
+
+                let mut transaction = Transaction {
                     id: transaction_id.clone(),
+                    from_account,
+                    to_account: account_keypair(to_shard, rng.gen_range(0..ACCOUNTS_PER_SHARD)).address(),
                     amount,
+                    fee: LOAD_GENERATOR_FEE,
+                    nonce,
                     from_shard: shard_index + 1,
                     to_shard,
+                    public_key: Default::default(),
+                    signature: Default::default(),
                     status: TransactionStatus::Pending,
                 };
+                transaction.sign(&sender);
 
                 tx_start_times.lock().unwrap().insert(transaction_id.clone(), Instant::now());
 
@@ -395,11 +722,12 @@ fn send_random_transactions(
                     transaction.id, transaction.from_shard, transaction.to_shard, transaction.status
                 );
 
+                // the sending shard debits first; cross-shard credits follow by gossip
                 let mut shards = shards.lock().unwrap();
-                if transaction.from_shard == transaction.to_shard {
-                    shards[shard_index].process_transactions(vec![transaction.clone()]);
-                } else {
-                    shards[shard_index].add_pending_cross_shard_tx(transaction.clone());
+                let result = shards[shard_index].process_transactions(vec![transaction.clone()]).remove(0);
+                if matches!(result, Err(ref e) if e.is_retryable()) {
+                    println!("Shard {} is full, will retry transaction {}", shard_index + 1, transaction.id);
+                    backlog.push_back(transaction);
                 }
 
                 tx_count += 1;
@@ -407,6 +735,11 @@ fn send_random_transactions(
 
             gossip_protocol.lock().unwrap().gossip(&mut shards.lock().unwrap());
 
+            // blocks are made by PoH slot, whether or not a shard got new transactions
+            for shard in shards.lock().unwrap().iter_mut() {
+                shard.check_and_create_block();
+            }
+
             let delay = rng.gen_range(200..500);
             *delay_in_ms.lock().unwrap() = delay as u64;
 
@@ -424,9 +757,13 @@ fn send_random_transactions(
                 for shard in shards_locked.iter_mut() {
                     if shard.check_epoch_transition() {
                         shard.transition_to_next_epoch();
-                        let checkpoint = shard.capture_checkpoint();
-                        println!("Captured checkpoint for Shard {} at Epoch {}", shard.id, shard.epoch);
-                        created_checkpoints.push(checkpoint);
+                        if let Some(checkpoint) = shard.finalize() {
+                            println!(
+                                "Captured checkpoint for Shard {} at block #{} in Epoch {}",
+                                shard.id, checkpoint.block_height, shard.epoch
+                            );
+                            created_checkpoints.push(checkpoint);
+                        }
                     }
                 }
                 created_checkpoints
@@ -439,6 +776,12 @@ fn send_random_transactions(
     });
 }
 
+// Simulated accounts get fixed test keys labelled by shard, so every run and
+// every node funds and signs for the same addresses.
+fn account_keypair(shard_id: usize, index: usize) -> Keypair {
+    keys::test_keypair(&format!("acct{}-{}", shard_id, index))
+}
+
 fn hash_to_shard(target: &str, shard_count: usize) -> usize {
     let mut hasher = DefaultHasher::new();
     target.hash(&mut hasher);
@@ -473,15 +816,23 @@ async fn main() -> std::io::Result<()> {
 
     match mode.as_str() {
         "bootstrap" => {
-            let bootstrap_port: u16 = args.get(2).map(|p| p.parse().unwrap_or(8081)).unwrap_or(8081);
+            let bootstrap_port: u16 = args.get(2).map(|p| p.parse().unwrap_or(BOOTSTRAP_TCP_PORT)).unwrap_or(BOOTSTRAP_TCP_PORT);
             let bootstrap_ip = "0.0.0.0";
             let bootstrap_node = BootstrapNode::new();
             println!("Starting bootstrap node on {}:{}", bootstrap_ip, bootstrap_port);
             bootstrap_node.start(bootstrap_port, bootstrap_ip);
         }
         "validator" => {
-            let port: u16 = args.get(2).map(|p| p.parse::<u16>().unwrap_or(8090)).unwrap_or(8090);
-            let bootstrap_port: u16 = args.get(3).map(|p| p.parse::<u16>().unwrap_or(8081)).unwrap_or(8081);
+            let port: u16 = args.get(2).map(|p| p.parse::<u16>().unwrap_or(WEB_SERVER_PORT)).unwrap_or(WEB_SERVER_PORT);
+            let bootstrap_port: u16 = args.get(3).map(|p| p.parse::<u16>().unwrap_or(BOOTSTRAP_TCP_PORT)).unwrap_or(BOOTSTRAP_TCP_PORT);
+            let fork_choice = match args.get(4).map(|rule| rule.parse::<ForkChoice>()) {
+                Some(Ok(rule)) => rule,
+                Some(Err(e)) => {
+                    eprintln!("{}. Please specify 'heaviest-weight' or 'longest-poh'.", e);
+                    std::process::exit(1);
+                }
+                None => ForkChoice::default(),
+            };
             let bootstrap_ip = "bootstrap";
 
             // Fetch the validator's IP or any identifier if needed
@@ -503,39 +854,91 @@ async fn main() -> std::io::Result<()> {
             let mut shards = Vec::new();
             let mut shard_infos = Vec::new();
 
+            let calibrated = PohConfig::load_or_calibrate(
+                std::path::Path::new(POH_CONFIG_PATH),
+                Duration::from_millis(DEFAULT_TARGET_SLOT_MS),
+                DEFAULT_TICKS_PER_SLOT,
+            )?;
+            // the measured rate is one core's; the shards' PoH threads split the cores
+            let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+            let shard_share = (cores as f64 * POH_CPU_SHARE / SHARD_COUNT as f64).min(1.0);
+            let poh_config = PohConfig::from_hash_rate(
+                calibrated.measured_hashes_per_second * shard_share,
+                calibrated.slot_duration(),
+                calibrated.ticks_per_slot,
+            );
+            println!(
+                "PoH config: {} hashes per tick, {} ticks per slot ({:.0} hashes/s measured, {:.0} per shard)",
+                poh_config.hashes_per_tick,
+                poh_config.ticks_per_slot,
+                calibrated.measured_hashes_per_second,
+                poh_config.measured_hashes_per_second
+            );
+
             // Setup shards and validators (use your logic here)
             let num_validators = 5;  // You can modify this value based on args or config
-            for i in 1..=10 {
+            for i in 1..=SHARD_COUNT {
                 let mut validators = Vec::new();
                 for j in 1..=num_validators {
                     let final_vote_weight = 0.9; // Modify as per your logic
                     validators.push(Validator::new(j, i, final_vote_weight));
                 }
-                shards.push(Shard::new(i, 100, MAX_TRANSACTIONS_PER_BLOCK, validators));
+                let mut shard = Shard::with_poh_config(
+                    i,
+                    100,
+                    MAX_TRANSACTIONS_PER_BLOCK,
+                    validators,
+                    poh_config,
+                    clock::system_clock(),
+                );
+                for n in 0..ACCOUNTS_PER_SHARD {
+                    shard
+                        .fund_account(&account_keypair(i, n).address(), GENESIS_BALANCE)
+                        .expect("genesis allocation overflows");
+                }
+                shard.set_fork_choice(fork_choice);
+                shard.set_mempool_config(MempoolConfig {
+                    max_transactions: MEMPOOL_MAX_TRANSACTIONS,
+                    max_credits: MEMPOOL_MAX_CREDITS,
+                    ..MempoolConfig::default()
+                });
+                shard.register_transaction_rule(Box::new(AmountBounds {
+                    min: DEFAULT_MIN_AMOUNT,
+                    max: MAX_TRANSFER_AMOUNT,
+                }));
+                shard.open_storage(std::path::Path::new(STORAGE_DIR), DEFAULT_SNAPSHOT_RETENTION)?;
+                shards.push(shard);
 
                 shard_infos.push(ShardInfo {
                     id: i,
                     ip: ip_address.clone(),
-                    port: port.clone(),
+                    port,
                 });
             }
 
             let shards = Arc::new(Mutex::new(shards));
             let gossip_protocol = Arc::new(Mutex::new(GossipProtocol::new()));
+            {
+                // shards restored from a snapshot tell the others where their chains now start
+                let mut shards = shards.lock().unwrap();
+                let restored: Vec<Checkpoint> = shards.iter().filter_map(|shard| shard.last_checkpoint.clone()).collect();
+                for checkpoint in &restored {
+                    gossip_protocol.lock().unwrap().gossip_checkpoints(checkpoint, &mut shards);
+                }
+            }
             let transaction_start_times = Arc::new(Mutex::new(HashMap::new()));
-            let block_gen_times = Arc::new(Mutex::new(Vec::new()));
             let transaction_batch_size = Arc::new(Mutex::new(1));
             let delay_in_ms = Arc::new(Mutex::new(200));
 
             let app_state = web::Data::new(AppState {
                 shards: Arc::clone(&shards),
                 transaction_start_times: Arc::clone(&transaction_start_times),
-                block_gen_times: Arc::clone(&block_gen_times),
                 shard_info: shard_infos,
                 nodes: Arc::new(Mutex::new(known_nodes)),  
                 start_time: Instant::now(),
                 transaction_batch_size: Arc::clone(&transaction_batch_size),
                 delay_in_ms: Arc::clone(&delay_in_ms),
+                elapsed_proof_limits: ElapsedProofLimits::for_config(&poh_config),
             });
 
             // Start random transactions and gossip
@@ -543,7 +946,6 @@ async fn main() -> std::io::Result<()> {
                 Arc::clone(&shards),
                 Arc::clone(&gossip_protocol),
                 Arc::clone(&transaction_start_times),
-                Arc::clone(&block_gen_times),
                 Arc::clone(&transaction_batch_size),
                 Arc::clone(&delay_in_ms),
             );
@@ -553,6 +955,15 @@ async fn main() -> std::io::Result<()> {
                     .app_data(app_state.clone())
                     .service(get_stats)
                     .service(get_nodes)
+                    .service(get_elapsed_proof)
+                    .service(verify_elapsed_proof)
+                    .service(get_balance_proof)
+                    .service(verify_balance_proof)
+                    .service(get_transaction)
+                    .service(get_transaction_status)
+                    .service(get_transaction_receipt)
+                    .service(verify_transaction_receipt)
+                    .service(receive_block)
                     .route("/", web::get().to(index))
                     .service(actix_files::Files::new("/static", "./static").show_files_listing())
             })
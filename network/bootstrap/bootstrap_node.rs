@@ -49,7 +49,7 @@ impl BootstrapNode {
             }
 
             // Check if we have registered nodes
-            if self.nodes.lock().unwrap().len() > 0 {
+            if !self.nodes.lock().unwrap().is_empty() {
                 break; // Exit the loop if we have at least one registered node
             }
         }
@@ -1,32 +1,71 @@
-use crate::shard::shard::{Shard, Transaction, Checkpoint};
+use crate::block::block::BlockHeader;
+use crate::codec::codec::{Decode, Encode};
+use crate::shard::shard::{Checkpoint, CrossShardTransfer, Shard};
 
-pub struct GossipProtocol {
-    pub known_shards: Vec<usize>,
-}
+pub struct GossipProtocol;
 
 impl GossipProtocol {
     pub fn new() -> Self {
-        GossipProtocol {
-            known_shards: Vec::new(),
-        }
+        GossipProtocol
     }
 
+    // Deliver every shard's outbox. Headers of new blocks go first, so the
+    // blocks that transfers prove their debits in are trusted by the time the
+    // transfers arrive. A transfer the receiving shard has no room for goes
+    // back to the sender's outbox for the next round.
     pub fn gossip(&mut self, shards: &mut [Shard]) {
-        let mut transactions_to_forward: Vec<Transaction> = Vec::new();
+        let headers: Vec<BlockHeader> = shards.iter_mut().flat_map(|shard| shard.drain_pending_headers()).collect();
+        for header in headers {
+            let encoded = header.to_bytes();
+            for shard in shards.iter_mut().filter(|shard| shard.id != header.shard_id) {
+                match BlockHeader::from_bytes(&encoded) {
+                    Ok(received) => {
+                        if !shard.receive_header(&received) {
+                            println!(
+                                "Gossip: Shard {} does not trust block #{} of Shard {}",
+                                shard.id, header.block_number, header.shard_id
+                            );
+                        }
+                    }
+                    Err(e) => println!("Gossip: Dropping undecodable header: {}", e),
+                }
+            }
+        }
+
+        let mut transfers_to_forward: Vec<CrossShardTransfer> = Vec::new();
 
         for shard in shards.iter_mut() {
-            transactions_to_forward.extend(shard.drain_pending_cross_shard_txs());
+            transfers_to_forward.extend(shard.drain_pending_cross_shard_txs());
         }
 
-        for tx in transactions_to_forward {
-            if let Some(target_shard) = shards.iter_mut().find(|s| s.id == tx.to_shard) {
-                println!(
-                    "Gossip: Forwarding transaction {} from Shard {} to Shard {}",
-                    tx.id, tx.from_shard, tx.to_shard
-                );
-                target_shard.process_cross_shard_transaction(tx);
-            } else {
+        for transfer in transfers_to_forward {
+            let tx = &transfer.transaction;
+            let Some(target_shard) = shards.iter_mut().find(|s| s.id == tx.to_shard) else {
                 println!("Gossip: No target shard found for transaction {}", tx.id);
+                continue;
+            };
+            println!(
+                "Gossip: Forwarding transaction {} from Shard {} to Shard {}",
+                tx.id, tx.from_shard, tx.to_shard
+            );
+            // forward the canonical wire bytes, as a remote shard would receive them
+            let result = match CrossShardTransfer::from_bytes(&transfer.to_bytes()) {
+                Ok(received) => target_shard.process_cross_shard_transaction(received),
+                Err(e) => {
+                    println!("Gossip: Dropping undecodable transaction {}: {}", tx.id, e);
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => {}
+                Err(e) if e.is_retryable() => {
+                    println!("Gossip: Shard {} has no room for transaction {}, holding it back", tx.to_shard, tx.id);
+                    let from_shard = tx.from_shard;
+                    if let Some(sender) = shards.iter_mut().find(|s| s.id == from_shard) {
+                        sender.hold_back_transfer(transfer);
+                    }
+                }
+                Err(e) => println!("Gossip: Shard {} refused transaction {}: {:?}", tx.to_shard, tx.id, e),
             }
         }
     }
@@ -43,13 +82,18 @@ impl GossipProtocol {
             checkpoint.shard_id
         );
 
+        let encoded = checkpoint.to_bytes();
+
         for shard in shards.iter_mut() {
             if shard.id != checkpoint.shard_id {
                 println!(
                     "Gossip: Sending checkpoint from Shard {} to Shard {}",
                     checkpoint.shard_id, shard.id
                 );
-                shard.receive_checkpoint(checkpoint.clone());
+                match Checkpoint::from_bytes(&encoded) {
+                    Ok(received) => shard.receive_checkpoint(received),
+                    Err(e) => println!("Gossip: Dropping undecodable checkpoint: {}", e),
+                }
             }
         }
     }
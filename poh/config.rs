@@ -0,0 +1,109 @@
+use crate::poh::entry;
+use crate::poh::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TICKS_PER_SLOT: u64 = 64;
+pub const DEFAULT_TARGET_SLOT_MS: u64 = 400;
+pub const CALIBRATION_SAMPLE: Duration = Duration::from_millis(250);
+
+// Hash-rate dependent PoH timing. `hashes_per_tick` is derived from the local
+// hash rate so that one slot of ticks takes roughly `target_slot_ms` to produce.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PohConfig {
+    pub hashes_per_tick: u64,
+    pub ticks_per_slot: u64,
+    pub target_slot_ms: u64,
+    pub measured_hashes_per_second: f64,
+}
+
+impl Default for PohConfig {
+    fn default() -> Self {
+        PohConfig {
+            hashes_per_tick: crate::poh::generator::DEFAULT_HASHES_PER_TICK,
+            ticks_per_slot: DEFAULT_TICKS_PER_SLOT,
+            target_slot_ms: DEFAULT_TARGET_SLOT_MS,
+            measured_hashes_per_second: 0.0,
+        }
+    }
+}
+
+impl PohConfig {
+    // Derive the tick size from a measured hash rate.
+    pub fn from_hash_rate(hashes_per_second: f64, target_slot: Duration, ticks_per_slot: u64) -> Self {
+        let ticks_per_slot = ticks_per_slot.max(1);
+        let hashes_per_slot = hashes_per_second * target_slot.as_secs_f64();
+        PohConfig {
+            hashes_per_tick: ((hashes_per_slot / ticks_per_slot as f64) as u64).max(1),
+            ticks_per_slot,
+            target_slot_ms: target_slot.as_millis() as u64,
+            measured_hashes_per_second: hashes_per_second,
+        }
+    }
+
+    pub fn calibrate(target_slot: Duration, ticks_per_slot: u64) -> Self {
+        Self::from_hash_rate(measure_hashes_per_second(CALIBRATION_SAMPLE), target_slot, ticks_per_slot)
+    }
+
+    pub fn slot_duration(&self) -> Duration {
+        Duration::from_millis(self.target_slot_ms)
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        self.slot_duration() / self.ticks_per_slot.max(1) as u32
+    }
+
+    pub fn hashes_per_slot(&self) -> u64 {
+        self.hashes_per_tick.saturating_mul(self.ticks_per_slot)
+    }
+
+    // The most an honest producer hashes between two entries: a slot's worth
+    // plus the hash that records the entry.
+    pub fn max_hashes_per_entry(&self) -> u64 {
+        self.hashes_per_slot().max(self.hashes_per_tick).saturating_add(1)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    // Reuse the config stored at `path` if it was calibrated for the same slot
+    // shape, otherwise calibrate on this machine and store the result.
+    pub fn load_or_calibrate(path: &Path, target_slot: Duration, ticks_per_slot: u64) -> io::Result<Self> {
+        if let Ok(config) = Self::load(path) {
+            if config.target_slot_ms == target_slot.as_millis() as u64 && config.ticks_per_slot == ticks_per_slot {
+                return Ok(config);
+            }
+        }
+
+        let config = Self::calibrate(target_slot, ticks_per_slot);
+        config.save(path)?;
+        Ok(config)
+    }
+}
+
+// Run the sequential PoH hash for about `sample` and report iterations per second.
+pub fn measure_hashes_per_second(sample: Duration) -> f64 {
+    const CHUNK: u64 = 1_000;
+
+    let started = Instant::now();
+    let mut hash = Hash::default();
+    let mut iterations = 0u64;
+    while started.elapsed() < sample {
+        hash = entry::extend_hash(&hash, CHUNK);
+        iterations += CHUNK;
+    }
+    // keep the chain observable so the loop is not optimised away
+    std::hint::black_box(hash);
+
+    iterations as f64 / started.elapsed().as_secs_f64()
+}
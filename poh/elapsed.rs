@@ -0,0 +1,233 @@
+use crate::poh::config::PohConfig;
+use crate::poh::entry::PohEntry;
+use crate::poh::hash::Hash;
+use crate::poh::verifier::{self, VerificationFailure};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MAX_PROOF_ENTRIES: usize = 10_000;
+pub const DEFAULT_MAX_PROOF_SLOTS: u64 = 150;
+
+// How much work verifying a proof from an untrusted source may take.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElapsedProofLimits {
+    pub max_entries: usize,
+    pub max_hashes_per_entry: u64,
+    pub max_total_hashes: u64,
+}
+
+impl ElapsedProofLimits {
+    // No entry may span more than a slot, and the whole proof no more than
+    // `DEFAULT_MAX_PROOF_SLOTS` slots.
+    pub fn for_config(config: &PohConfig) -> Self {
+        ElapsedProofLimits {
+            max_entries: DEFAULT_MAX_PROOF_ENTRIES,
+            max_hashes_per_entry: config.max_hashes_per_entry(),
+            max_total_hashes: config.max_hashes_per_entry().saturating_mul(DEFAULT_MAX_PROOF_SLOTS),
+        }
+    }
+}
+
+// Shows that `first_tx` was recorded before `second_tx` and that at least
+// `num_hashes` sequential hash iterations separate their entries. `entries`
+// runs from the entry holding `first_tx` through the entry holding
+// `second_tx`, and `start_hash` is the hash the first of them extends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElapsedProof {
+    pub first_tx: String,
+    pub second_tx: String,
+    pub start_hash: Hash,
+    pub entries: Vec<PohEntry>,
+    pub num_hashes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElapsedProofError {
+    NoEntries,
+    TooManyEntries { count: usize, max: usize },
+    TooManyHashes { max: u64 }, // the entries add up to more than `max` hashes, or overflow
+    MissingTransaction(String),
+    OutOfOrder,
+    Chain(VerificationFailure),
+    HashCountMismatch { expected: u64, found: u64 },
+}
+
+impl ElapsedProof {
+    // Build a proof from a contiguous run of entries. `start_hash` is the hash
+    // of the entry just before `entries[0]`.
+    pub fn new(first_tx: &str, second_tx: &str, start_hash: Hash, entries: Vec<PohEntry>) -> Self {
+        // a real chain never gets near overflowing; saturate rather than panic
+        let num_hashes = hashes_between(&entries).unwrap_or(u64::MAX);
+        ElapsedProof {
+            first_tx: first_tx.to_string(),
+            second_tx: second_tx.to_string(),
+            start_hash,
+            entries,
+            num_hashes,
+        }
+    }
+
+    // Check the proof on its own, returning the number of hash iterations
+    // proven to lie between the two transactions. Proofs beyond `limits` are
+    // refused before any hashing is done.
+    pub fn verify(&self, limits: &ElapsedProofLimits) -> Result<u64, ElapsedProofError> {
+        let (first, last) = match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(ElapsedProofError::NoEntries),
+        };
+        if self.entries.len() > limits.max_entries {
+            return Err(ElapsedProofError::TooManyEntries {
+                count: self.entries.len(),
+                max: limits.max_entries,
+            });
+        }
+        let total_hashes = match self
+            .entries
+            .iter()
+            .try_fold(0u64, |total, entry| total.checked_add(entry.num_hashes))
+        {
+            Some(total) if total <= limits.max_total_hashes => total,
+            _ => {
+                return Err(ElapsedProofError::TooManyHashes {
+                    max: limits.max_total_hashes,
+                })
+            }
+        };
+        verifier::check_hash_counts(&self.entries, limits.max_hashes_per_entry).map_err(ElapsedProofError::Chain)?;
+
+        let first_position = position(first, &self.first_tx)?;
+        let second_position = position(last, &self.second_tx)?;
+        if self.entries.len() == 1 && first_position >= second_position {
+            return Err(ElapsedProofError::OutOfOrder);
+        }
+
+        verifier::verify_entries(&self.start_hash, &self.entries).map_err(ElapsedProofError::Chain)?;
+
+        let expected = total_hashes - first.num_hashes;
+        if expected != self.num_hashes {
+            return Err(ElapsedProofError::HashCountMismatch {
+                expected,
+                found: self.num_hashes,
+            });
+        }
+        Ok(expected)
+    }
+}
+
+fn position(entry: &PohEntry, tx_id: &str) -> Result<usize, ElapsedProofError> {
+    entry
+        .transactions
+        .iter()
+        .position(|tx| tx == tx_id)
+        .ok_or_else(|| ElapsedProofError::MissingTransaction(tx_id.to_string()))
+}
+
+// Iterations performed after the first entry was recorded, up to and including
+// the last entry, or None if they overflow.
+fn hashes_between(entries: &[PohEntry]) -> Option<u64> {
+    entries
+        .iter()
+        .skip(1)
+        .try_fold(0u64, |total, entry| total.checked_add(entry.num_hashes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poh::generator::PohGenerator;
+    use crate::poh::verifier::EntryFault;
+
+    fn config() -> PohConfig {
+        PohConfig {
+            hashes_per_tick: 4,
+            ticks_per_slot: 2,
+            ..PohConfig::default()
+        }
+    }
+
+    // "a" and "b" recorded two ticks apart, starting from the default hash.
+    fn proof() -> ElapsedProof {
+        let mut generator = PohGenerator::with_config(10, config(), crate::poh::clock::system_clock());
+        let mut entries = vec![generator.record(vec!["a".to_string()], Hash::default())];
+        entries.push(generator.tick());
+        entries.push(generator.tick());
+        entries.push(generator.record(vec!["b".to_string()], Hash::default()));
+        ElapsedProof::new("a", "b", Hash::default(), entries)
+    }
+
+    fn limits() -> ElapsedProofLimits {
+        ElapsedProofLimits::for_config(&config())
+    }
+
+    #[test]
+    fn honest_proof_verifies() {
+        assert_eq!(proof().verify(&limits()), Ok(9));
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let mut inflated = proof();
+        inflated.num_hashes += 1;
+        assert_eq!(
+            inflated.verify(&limits()),
+            Err(ElapsedProofError::HashCountMismatch { expected: 9, found: 10 })
+        );
+
+        let mut stretched = proof();
+        stretched.entries[1].num_hashes += 1;
+        stretched.num_hashes += 1;
+        assert!(matches!(
+            stretched.verify(&limits()),
+            Err(ElapsedProofError::Chain(VerificationFailure {
+                index: 1,
+                fault: EntryFault::HashMismatch { .. }
+            }))
+        ));
+
+        let mut shortened = proof();
+        shortened.entries.remove(1);
+        assert!(matches!(
+            shortened.verify(&limits()),
+            Err(ElapsedProofError::Chain(VerificationFailure { index: 1, .. }))
+        ));
+
+        let mut renamed = proof();
+        renamed.entries[3].transactions = vec!["c".to_string()];
+        renamed.second_tx = "c".to_string();
+        assert!(matches!(
+            renamed.verify(&limits()),
+            Err(ElapsedProofError::Chain(VerificationFailure {
+                index: 3,
+                fault: EntryFault::TransactionsRootMismatch { .. }
+            }))
+        ));
+
+        let mut reversed = proof();
+        std::mem::swap(&mut reversed.first_tx, &mut reversed.second_tx);
+        assert_eq!(
+            reversed.verify(&limits()),
+            Err(ElapsedProofError::MissingTransaction("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn oversized_proofs_are_refused_before_hashing() {
+        let mut padded = proof();
+        padded.entries[1].num_hashes = limits().max_total_hashes;
+        assert_eq!(
+            padded.verify(&limits()),
+            Err(ElapsedProofError::TooManyHashes {
+                max: limits().max_total_hashes
+            })
+        );
+
+        let mut long_entry = proof();
+        long_entry.entries[1].num_hashes = limits().max_hashes_per_entry + 1;
+        assert!(matches!(
+            long_entry.verify(&limits()),
+            Err(ElapsedProofError::Chain(VerificationFailure {
+                index: 1,
+                fault: EntryFault::TooManyHashes { .. }
+            }))
+        ));
+    }
+}
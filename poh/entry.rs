@@ -1,49 +1,183 @@
-use chrono::{DateTime, Utc, TimeZone};
-use sha2::{Sha256, Digest};
-use std::fmt::Write;
+use crate::codec::codec::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::poh::hash::Hash;
+use crate::poh::merkle::{self, MerkleProof, MerkleTree};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PohEntry {
+    pub num_hashes: u64, // hash iterations since the previous entry, including this one
     pub transactions: Vec<String>,
+    pub transactions_root: Hash, // Merkle root over `transactions`, committed in `hash`
+    pub events: Vec<Hash>, // hashes stamped into the chain as they happened, such as a transaction arriving
+    pub performance_digest: Hash, // validator performance set at the time of recording, committed in `hash`
     pub timestamp: i64,
     pub readable_timestamp: String,
-    pub hash: String,
+    pub hash: Hash,
 }
 
 impl PohEntry {
-    pub fn new(transactions: Vec<String>, prev_hash: &str) -> Self {
-        // Directly get the current timestamp from Utc::now()
-        let current_time: DateTime<Utc> = Utc::now();
+    // `prev_hash` is the running chain state after `num_hashes - 1` iterations since
+    // the previous entry; the final iteration either mixes in the transactions and
+    // events or, for a tick, is a plain hash. `current_time` comes from the
+    // generator's clock.
+    pub fn new(
+        num_hashes: u64,
+        transactions: Vec<String>,
+        events: Vec<Hash>,
+        performance_digest: Hash,
+        prev_hash: &Hash,
+        current_time: DateTime<Utc>,
+    ) -> Self {
         let timestamp = current_time.timestamp();
-        
-        // Format the timestamp to a readable string directly
-        let readable_timestamp = current_time.format("%Y-%m-%d %H:%M:%S").to_string();
 
-        let mut hasher = Sha256::new();
-        for tx in &transactions {
-            hasher.update(tx);
-        }
-        hasher.update(prev_hash);
-        hasher.update(timestamp.to_string());
-        let result = hasher.finalize();
-        let mut hash_str = String::new();
-        for byte in result {
-            write!(&mut hash_str, "{:02x}", byte).expect("Unable to write");
-        }
+        let readable_timestamp = readable(current_time);
+
+        let transactions_root = merkle::root(&transactions);
+        let hash = record_hash(prev_hash, &transactions, &transactions_root, &events, &performance_digest, timestamp);
 
         PohEntry {
+            num_hashes,
             transactions,
+            transactions_root,
+            events,
+            performance_digest,
             timestamp,
             readable_timestamp,
-            hash: hash_str,
+            hash,
         }
     }
 
-    pub fn validate_transaction(tx: &str) -> Result<(), &'static str> {
-        if tx.is_empty() {
-            return Err("Transaction is empty");
+    #[cfg(test)]
+    pub fn is_tick(&self) -> bool {
+        self.transactions.is_empty() && self.events.is_empty()
+    }
+
+    // Inclusion proof for `tx_id` against `transactions_root`, if the entry holds it.
+    pub fn prove_transaction(&self, tx_id: &str) -> Option<MerkleProof> {
+        let index = self.transactions.iter().position(|tx| tx == tx_id)?;
+        MerkleTree::new(&self.transactions).prove(index)
+    }
+
+    // Check a proof without needing the entry's transaction list.
+    pub fn verify_transaction_proof(&self, tx_id: &str, proof: &MerkleProof) -> bool {
+        proof.verify(tx_id.as_bytes(), &self.transactions_root)
+    }
+}
+
+// A single iteration of the PoH chain.
+pub fn hash_once(prev_hash: &Hash) -> Hash {
+    Hash::digest(prev_hash.as_ref())
+}
+
+// Iterates the PoH chain `num_hashes` times starting from `prev_hash`.
+pub fn extend_hash(prev_hash: &Hash, num_hashes: u64) -> Hash {
+    let mut hash = *prev_hash;
+    for _ in 0..num_hashes {
+        hash = hash_once(&hash);
+    }
+    hash
+}
+
+// Digest of an entry's payload that gets mixed into the chain.
+pub fn mixin_hash(transactions_root: &Hash, events: &[Hash], performance_digest: &Hash, timestamp: i64) -> Hash {
+    let mut encoder = Encoder::versioned();
+    encoder.put_hash(transactions_root);
+    encoder.put_seq(events.iter());
+    encoder.put_hash(performance_digest);
+    encoder.put_i64(timestamp);
+    Hash::digest(&encoder.finish())
+}
+
+// Final iteration of an entry: a plain hash for ticks, otherwise the chain state
+// hashed together with the entry's mixin.
+pub fn record_hash(
+    prev_hash: &Hash,
+    transactions: &[String],
+    transactions_root: &Hash,
+    events: &[Hash],
+    performance_digest: &Hash,
+    timestamp: i64,
+) -> Hash {
+    if transactions.is_empty() && events.is_empty() {
+        return hash_once(prev_hash);
+    }
+
+    let mixin = mixin_hash(transactions_root, events, performance_digest, timestamp);
+    Hash::hashv(&[prev_hash.as_ref(), mixin.as_ref()])
+}
+
+fn readable(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+// `readable_timestamp` is derived from `timestamp` and is not encoded.
+impl Encode for PohEntry {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.num_hashes);
+        encoder.put_seq(self.transactions.iter());
+        encoder.put_hash(&self.transactions_root);
+        encoder.put_seq(self.events.iter());
+        encoder.put_hash(&self.performance_digest);
+        encoder.put_i64(self.timestamp);
+        encoder.put_hash(&self.hash);
+    }
+}
+
+impl Decode for PohEntry {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let num_hashes = decoder.u64()?;
+        let transactions = decoder.seq()?;
+        let transactions_root = decoder.hash()?;
+        let events = decoder.seq()?;
+        let performance_digest = decoder.hash()?;
+        let timestamp = decoder.i64()?;
+        let hash = decoder.hash()?;
+        let time = DateTime::<Utc>::from_timestamp(timestamp, 0)
+            .ok_or(DecodeError::InvalidValue("entry timestamp out of range"))?;
+
+        Ok(PohEntry {
+            num_hashes,
+            transactions,
+            transactions_root,
+            events,
+            performance_digest,
+            timestamp,
+            readable_timestamp: readable(time),
+            hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn recorded(transactions: &[&str]) -> PohEntry {
+        let transactions = transactions.iter().map(|tx| tx.to_string()).collect();
+        let time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        PohEntry::new(5, transactions, Vec::new(), Hash::default(), &Hash::digest(b"prev"), time)
+    }
+
+    #[test]
+    fn proves_each_recorded_transaction() {
+        let entry = recorded(&["tx1", "tx2", "tx3"]);
+        for tx_id in &entry.transactions {
+            let proof = entry.prove_transaction(tx_id).unwrap();
+            assert!(entry.verify_transaction_proof(tx_id, &proof));
         }
-        // More validation rules can be added here.
-        Ok(())
+        assert!(entry.prove_transaction("tx4").is_none());
+        assert!(recorded(&[]).prove_transaction("tx1").is_none());
+    }
+
+    #[test]
+    fn proof_is_bound_to_the_id_and_the_entry() {
+        let entry = recorded(&["tx1", "tx2", "tx3"]);
+        let proof = entry.prove_transaction("tx2").unwrap();
+        assert!(!entry.verify_transaction_proof("tx3", &proof));
+
+        let other = recorded(&["tx1", "tx2", "tx4"]);
+        assert!(!other.verify_transaction_proof("tx2", &proof));
     }
 }
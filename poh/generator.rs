@@ -1,40 +1,101 @@
-use crate::poh::entry::PohEntry;
-use crate::validator::validator::ValidatorPerformance;
+use crate::poh::clock::{self, SharedClock};
+use crate::poh::config::PohConfig;
+use crate::poh::entry::{self, PohEntry};
+use crate::poh::hash::Hash;
+use crate::validator::validator::{self, ValidatorPerformance};
 use std::collections::HashMap;
 
+pub const DEFAULT_HASHES_PER_TICK: u64 = 2_000;
+
 #[derive(Debug)]
 pub struct PohGenerator {
-    pub previous_hash: String,
+    pub previous_hash: Hash,   // current head of the hash chain
+    pub num_hashes: u64,       // iterations since the last emitted entry
+    pub hashes_per_tick: u64,
+    pub ticks_per_slot: u64,
+    pub tick_height: u64,      // ticks emitted since the generator started
     pub batch_size: usize,
+    clock: SharedClock,
 }
 
 impl PohGenerator {
+    #[allow(dead_code)] // the node builds its generators from a calibrated config
     pub fn new(batch_size: usize) -> Self {
+        Self::with_config(batch_size, PohConfig::default(), clock::system_clock())
+    }
+
+    pub fn with_config(batch_size: usize, config: PohConfig, clock: SharedClock) -> Self {
         PohGenerator {
-            previous_hash: String::from("0"),
+            previous_hash: Hash::default(),
+            num_hashes: 0,
+            hashes_per_tick: config.hashes_per_tick,
+            ticks_per_slot: config.ticks_per_slot,
+            tick_height: 0,
             batch_size,
+            clock,
         }
     }
 
+    // Move the head of the chain, e.g. back to the last committed entry when a
+    // batch of generated entries is thrown away.
+    pub fn reset(&mut self, hash: Hash) {
+        self.previous_hash = hash;
+        self.num_hashes = 0;
+    }
+
+    // Advance the chain by `num_hashes` iterations without emitting an entry.
+    pub fn hash(&mut self, num_hashes: u64) {
+        self.previous_hash = entry::extend_hash(&self.previous_hash, num_hashes);
+        self.num_hashes += num_hashes;
+    }
+
+    // Emit an empty entry after a full tick's worth of hashing.
+    #[cfg(test)]
+    pub fn tick(&mut self) -> PohEntry {
+        self.hash(self.hashes_per_tick.saturating_sub(1));
+        self.record_tick()
+    }
+
+    // Emit an empty entry at the current position, whatever has been hashed so far.
+    pub fn record_tick(&mut self) -> PohEntry {
+        self.tick_height += 1;
+        self.record(Vec::new(), Hash::default())
+    }
+
+    // Mix `transactions` and the validator performance digest into the chain at
+    // the current position and emit the entry.
+    pub fn record(&mut self, transactions: Vec<String>, performance_digest: Hash) -> PohEntry {
+        self.emit(transactions, Vec::new(), performance_digest)
+    }
+
+    // Stamp `events` into the chain at the current position.
+    pub fn record_events(&mut self, events: Vec<Hash>) -> PohEntry {
+        self.emit(Vec::new(), events, Hash::default())
+    }
+
+    fn emit(&mut self, transactions: Vec<String>, events: Vec<Hash>, performance_digest: Hash) -> PohEntry {
+        let entry = PohEntry::new(
+            self.num_hashes + 1,
+            transactions,
+            events,
+            performance_digest,
+            &self.previous_hash,
+            self.clock.now(),
+        );
+        self.previous_hash = entry.hash;
+        self.num_hashes = 0;
+        entry
+    }
+
     pub fn generate_entry(
         &mut self,
         transactions: Vec<String>,
         validator_performance: &HashMap<usize, ValidatorPerformance>,
-    ) -> Result<PohEntry, &'static str> {
-        for tx in &transactions {
-            PohEntry::validate_transaction(tx)?;
-        }
-
-        let entry = PohEntry::new(transactions, &self.previous_hash);
-
-        // update the poh hash based on validator performance
-        for performance in validator_performance.values() {
-            let contribution_factor = performance.honesty_score * (1.0 / (performance.response_time + 1.0));
-            self.previous_hash = format!("{}:{}", self.previous_hash, contribution_factor);
-        }
+    ) -> PohEntry {
+        // time has to pass on the chain before the batch can be recorded
+        self.hash(self.hashes_per_tick);
 
-        self.previous_hash = entry.hash.clone();
-        Ok(entry)
+        self.record(transactions, validator::performance_digest(validator_performance))
     }
 
     pub fn generate_entries(
@@ -47,7 +108,7 @@ impl PohGenerator {
         }
         let mut entries = Vec::new();
         for chunk in transactions.chunks(self.batch_size) {
-            let entry = self.generate_entry(chunk.to_vec(), &validator_performance)?;
+            let entry = self.generate_entry(chunk.to_vec(), &validator_performance);
             entries.push(entry);
         }
         Ok(entries)
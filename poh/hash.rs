@@ -0,0 +1,134 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+pub const HASH_BYTES: usize = 32;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+// Write `bytes` as lowercase hex, two digits per byte. Hashes, keys and
+// addresses are all rendered through this.
+pub fn write_hex<W: fmt::Write>(out: &mut W, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        out.write_char(HEX_DIGITS[(byte >> 4) as usize] as char)?;
+        out.write_char(HEX_DIGITS[(byte & 0x0f) as usize] as char)?;
+    }
+    Ok(())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    write_hex(&mut hex, bytes).expect("writing to a String cannot fail");
+    hex
+}
+
+// A SHA-256 digest. Kept as raw bytes everywhere and only rendered as hex for
+// display and serialization.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Hash(pub [u8; HASH_BYTES]);
+
+impl Hash {
+    pub fn digest(data: &[u8]) -> Self {
+        Hash(Sha256::digest(data).into())
+    }
+
+    // Hash several byte slices as if they were concatenated.
+    pub fn hashv(parts: &[&[u8]]) -> Self {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        Hash(hasher.finalize().into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_BYTES] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, &self.0)
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseHashError {
+    InvalidLength(usize),
+    InvalidHexDigit(char),
+}
+
+impl fmt::Display for ParseHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHashError::InvalidLength(len) => {
+                write!(f, "expected {} hex characters, got {}", HASH_BYTES * 2, len)
+            }
+            ParseHashError::InvalidHexDigit(c) => write!(f, "invalid hex digit '{}'", c),
+        }
+    }
+}
+
+impl FromStr for Hash {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != HASH_BYTES * 2 {
+            return Err(ParseHashError::InvalidLength(s.len()));
+        }
+        let mut bytes = [0u8; HASH_BYTES];
+        for (i, pair) in s.as_bytes().chunks(2).enumerate() {
+            let high = hex_digit(pair[0])?;
+            let low = hex_digit(pair[1])?;
+            bytes[i] = (high << 4) | low;
+        }
+        Ok(Hash(bytes))
+    }
+}
+
+fn hex_digit(byte: u8) -> Result<u8, ParseHashError> {
+    (byte as char)
+        .to_digit(16)
+        .map(|digit| digit as u8)
+        .ok_or(ParseHashError::InvalidHexDigit(byte as char))
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HashVisitor;
+
+        impl Visitor<'_> for HashVisitor {
+            type Value = Hash;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a {}-character hex string", HASH_BYTES * 2)
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Hash, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(HashVisitor)
+    }
+}
@@ -0,0 +1,191 @@
+use crate::codec::codec::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::poh::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+// Leaves and interior nodes are hashed under different prefixes so a leaf can
+// never be passed off as an interior node.
+const LEAF_PREFIX: &[u8] = &[0];
+const NODE_PREFIX: &[u8] = &[1];
+
+pub fn hash_leaf(data: &[u8]) -> Hash {
+    Hash::hashv(&[LEAF_PREFIX, data])
+}
+
+pub fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    Hash::hashv(&[NODE_PREFIX, left.as_ref(), right.as_ref()])
+}
+
+// Binary Merkle tree kept level by level, leaves first. An unpaired node at the
+// end of a level is promoted unchanged rather than duplicated, so two different
+// leaf lists can never share a root.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new<T: AsRef<[u8]>>(items: &[T]) -> Self {
+        let mut levels = vec![items.iter().map(|item| hash_leaf(item.as_ref())).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    // The root of an empty tree is the zero hash.
+    pub fn root(&self) -> Hash {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn prove(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                path.push(if sibling < position {
+                    ProofNode::Left(level[sibling])
+                } else {
+                    ProofNode::Right(level[sibling])
+                });
+            }
+            position /= 2;
+        }
+        Some(MerkleProof { index, path })
+    }
+}
+
+pub fn root<T: AsRef<[u8]>>(items: &[T]) -> Hash {
+    MerkleTree::new(items).root()
+}
+
+// A sibling on the path from a leaf to the root, tagged with the side it sits on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProofNode {
+    Left(Hash),
+    Right(Hash),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize, // position of the proven leaf
+    pub path: Vec<ProofNode>,
+}
+
+impl MerkleProof {
+    pub fn compute_root(&self, leaf: &[u8]) -> Hash {
+        self.path.iter().fold(hash_leaf(leaf), |acc, node| match node {
+            ProofNode::Left(sibling) => hash_node(sibling, &acc),
+            ProofNode::Right(sibling) => hash_node(&acc, sibling),
+        })
+    }
+
+    pub fn verify(&self, leaf: &[u8], root: &Hash) -> bool {
+        self.compute_root(leaf) == *root
+    }
+}
+
+impl Encode for ProofNode {
+    fn encode(&self, encoder: &mut Encoder) {
+        let (tag, sibling) = match self {
+            ProofNode::Left(sibling) => (0, sibling),
+            ProofNode::Right(sibling) => (1, sibling),
+        };
+        encoder.put_u8(tag);
+        encoder.put_hash(sibling);
+    }
+}
+
+impl Decode for ProofNode {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        match decoder.u8()? {
+            0 => Ok(ProofNode::Left(decoder.hash()?)),
+            1 => Ok(ProofNode::Right(decoder.hash()?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for MerkleProof {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_usize(self.index);
+        encoder.put_seq(self.path.iter());
+    }
+}
+
+impl Decode for MerkleProof {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(MerkleProof {
+            index: decoder.usize()?,
+            path: decoder.seq()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("tx{}", i)).collect()
+    }
+
+    #[test]
+    fn proves_every_leaf() {
+        for count in 1..=9 {
+            let items = leaves(count);
+            let tree = MerkleTree::new(&items);
+            for (index, item) in items.iter().enumerate() {
+                let proof = tree.prove(index).unwrap();
+                assert!(proof.verify(item.as_bytes(), &tree.root()), "leaf {} of {}", index, count);
+                assert!(!proof.verify(b"other", &tree.root()));
+            }
+            assert!(tree.prove(count).is_none());
+        }
+    }
+
+    #[test]
+    fn proof_fails_against_another_root() {
+        let tree = MerkleTree::new(&leaves(5));
+        let other = MerkleTree::new(&leaves(6));
+        let proof = tree.prove(2).unwrap();
+        assert!(!proof.verify(b"tx2", &other.root()));
+    }
+
+    #[test]
+    fn unpaired_leaf_is_not_duplicated() {
+        let three = leaves(3);
+        let mut padded = three.clone();
+        padded.push(three[2].clone());
+        assert_ne!(root(&three), root(&padded));
+        assert_eq!(root::<String>(&[]), Hash::default());
+    }
+
+    #[test]
+    fn proof_round_trips() {
+        let proof = MerkleTree::new(&leaves(7)).prove(4).unwrap();
+        assert_eq!(MerkleProof::from_bytes(&proof.to_bytes()), Ok(proof));
+    }
+}
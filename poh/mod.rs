@@ -1,2 +1,10 @@
+pub mod clock;
+pub mod config;
+pub mod elapsed;
 pub mod entry;
-pub mod generator;
\ No newline at end of file
+pub mod generator;
+pub mod hash;
+pub mod merkle;
+pub mod service;
+pub mod sparse_merkle;
+pub mod verifier;
@@ -0,0 +1,277 @@
+use crate::poh::clock::SharedClock;
+use crate::poh::entry::PohEntry;
+use crate::poh::generator::PohGenerator;
+use crate::poh::hash::Hash;
+use crate::validator::validator::ValidatorPerformance;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const STOPPED: &str = "PoH service stopped";
+
+enum PohCommand {
+    Record {
+        event_hash: Hash,
+        reply: Sender<PohEntry>,
+    },
+    RecordTransactions {
+        transactions: Vec<String>,
+        validator_performance: HashMap<usize, ValidatorPerformance>,
+        reply: Sender<Result<Vec<PohEntry>, &'static str>>,
+    },
+    Sync(Sender<u64>),
+    Subscribe(Sender<PohEntry>),
+    Reset {
+        hash: Hash,
+        subscriber: Sender<PohEntry>,
+    },
+    Stop,
+}
+
+// Runs a generator on its own thread, hashing continuously and emitting a tick
+// every `tick_duration` of `clock` time. Events are mixed in as they are
+// recorded, and every entry, tick or record, is sent to all subscribers in
+// chain order.
+//
+// The thread finishes a tick's worth of hashing before it takes the next
+// request or emits anything, so where an entry lands depends only on the
+// order of requests and the clock, not on thread timing. Runs on a mock clock
+// therefore replay exactly.
+#[derive(Debug)]
+pub struct PohService {
+    commands: Sender<PohCommand>,
+    thread: Option<JoinHandle<PohGenerator>>,
+}
+
+impl PohService {
+    pub fn start(generator: PohGenerator, tick_duration: Duration, clock: SharedClock) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        // the first tick is due a tick from now, not from whenever the thread gets going
+        let started = clock.now();
+        let thread = thread::Builder::new()
+            .name("poh-service".to_string())
+            .spawn(move || run(generator, tick_duration, clock, started, receiver))
+            .expect("failed to spawn PoH service thread");
+
+        PohService {
+            commands,
+            thread: Some(thread),
+        }
+    }
+
+    // Stamp a single event into the chain, returning the entry that records it.
+    pub fn record(&self, event_hash: Hash) -> Result<PohEntry, &'static str> {
+        let (reply, entry) = mpsc::channel();
+        self.send(PohCommand::Record { event_hash, reply })?;
+        entry.recv().map_err(|_| STOPPED)
+    }
+
+    // Record a batch of transaction ids, in entries of the generator's batch size.
+    pub fn record_transactions(
+        &self,
+        transactions: Vec<String>,
+        validator_performance: HashMap<usize, ValidatorPerformance>,
+    ) -> Result<Vec<PohEntry>, &'static str> {
+        let (reply, entries) = mpsc::channel();
+        self.send(PohCommand::RecordTransactions {
+            transactions,
+            validator_performance,
+            reply,
+        })?;
+        entries.recv().map_err(|_| STOPPED)?
+    }
+
+    // Wait until every tick due by now has been sent to subscribers, and
+    // return the tick height.
+    pub fn sync(&self) -> Result<u64, &'static str> {
+        let (reply, tick_height) = mpsc::channel();
+        self.send(PohCommand::Sync(reply))?;
+        tick_height.recv().map_err(|_| STOPPED)
+    }
+
+    // The subscription sees every entry from the time of the call on.
+    pub fn subscribe(&self) -> Receiver<PohEntry> {
+        let (sender, receiver) = mpsc::channel();
+        self.register(PohCommand::Subscribe(sender));
+        receiver
+    }
+
+    // Continue the chain from `hash`. The returned subscription starts with
+    // the first entry after the reset; older subscriptions see the chain jump.
+    pub fn reset(&self, hash: Hash) -> Receiver<PohEntry> {
+        let (subscriber, receiver) = mpsc::channel();
+        self.register(PohCommand::Reset { hash, subscriber });
+        receiver
+    }
+
+    // Send a command that adds a subscriber and wait until the thread has
+    // taken it, so the clock cannot move on before it is in place. If the
+    // service has stopped the subscriber is dropped and its receiver simply
+    // reports a disconnect.
+    fn register(&self, command: PohCommand) {
+        if self.send(command).is_ok() {
+            let _ = self.sync();
+        }
+    }
+
+    fn send(&self, command: PohCommand) -> Result<(), &'static str> {
+        self.commands.send(command).map_err(|_| STOPPED)
+    }
+
+    fn shutdown(&mut self) -> Option<PohGenerator> {
+        let thread = self.thread.take()?;
+        let _ = self.commands.send(PohCommand::Stop);
+        Some(thread.join().expect("PoH service thread panicked"))
+    }
+}
+
+impl Drop for PohService {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run(
+    mut generator: PohGenerator,
+    tick_duration: Duration,
+    clock: SharedClock,
+    started: DateTime<Utc>,
+    commands: Receiver<PohCommand>,
+) -> PohGenerator {
+    let tick_length = chrono::Duration::from_std(tick_duration).expect("tick duration out of range");
+    // more than a slot behind, as after a suspend, the missed ticks are skipped
+    let max_lag = tick_length * generator.ticks_per_slot.max(1) as i32;
+    let mut subscribers: Vec<Sender<PohEntry>> = Vec::new();
+    let mut next_tick = started + tick_length;
+
+    loop {
+        fill_tick(&mut generator);
+        let wait = (next_tick - clock.now()).to_std().unwrap_or(Duration::ZERO);
+        let command = match commands.recv_timeout(wait.min(tick_duration)) {
+            Ok(command) => Some(command),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return generator,
+        };
+
+        // ticks that fell due come before the request
+        let now = clock.now();
+        if now - next_tick > max_lag {
+            next_tick = now;
+        }
+        while next_tick <= now {
+            let tick = generator.record_tick();
+            broadcast(&mut subscribers, &tick);
+            fill_tick(&mut generator);
+            next_tick += tick_length;
+        }
+
+        match command {
+            Some(PohCommand::Record { event_hash, reply }) => {
+                let entry = generator.record_events(vec![event_hash]);
+                broadcast(&mut subscribers, &entry);
+                let _ = reply.send(entry);
+            }
+            Some(PohCommand::RecordTransactions {
+                transactions,
+                validator_performance,
+                reply,
+            }) => {
+                let entries = generator.generate_entries(transactions, validator_performance);
+                for entry in entries.iter().flatten() {
+                    broadcast(&mut subscribers, entry);
+                }
+                let _ = reply.send(entries);
+            }
+            Some(PohCommand::Sync(reply)) => {
+                let _ = reply.send(generator.tick_height);
+            }
+            Some(PohCommand::Subscribe(sender)) => subscribers.push(sender),
+            Some(PohCommand::Reset { hash, subscriber }) => {
+                generator.reset(hash);
+                subscribers.push(subscriber);
+            }
+            Some(PohCommand::Stop) => return generator,
+            None => {}
+        }
+    }
+}
+
+// Hash up to one short of a tick, the point where the next entry is recorded.
+fn fill_tick(generator: &mut PohGenerator) {
+    let remaining = generator
+        .hashes_per_tick
+        .saturating_sub(1)
+        .saturating_sub(generator.num_hashes);
+    generator.hash(remaining);
+}
+
+fn broadcast(subscribers: &mut Vec<Sender<PohEntry>>, entry: &PohEntry) {
+    subscribers.retain(|subscriber| subscriber.send(entry.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poh::clock::MockClock;
+    use crate::poh::config::PohConfig;
+    use chrono::TimeZone;
+    use std::sync::Arc;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn start(clock: &Arc<MockClock>) -> PohService {
+        let config = PohConfig {
+            hashes_per_tick: 8,
+            ticks_per_slot: 4,
+            ..PohConfig::default()
+        };
+        let generator = PohGenerator::with_config(4, config, clock.clone());
+        PohService::start(generator, TICK, clock.clone())
+    }
+
+    fn mock_clock() -> Arc<MockClock> {
+        Arc::new(MockClock::new(chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap()))
+    }
+
+    // Ticks as the clock moves, with an arrival stamped in between.
+    fn run_once() -> Vec<PohEntry> {
+        let clock = mock_clock();
+        let service = start(&clock);
+        let entries = service.subscribe();
+        clock.advance(TICK * 2);
+        let stamp = service.record(Hash::digest(b"tx1")).unwrap();
+        assert_eq!(stamp.events, vec![Hash::digest(b"tx1")]);
+        clock.advance(TICK);
+        assert_eq!(service.sync().unwrap(), 3);
+        let recorded = entries.try_iter().collect();
+        drop(service);
+        recorded
+    }
+
+    #[test]
+    fn ticks_follow_the_clock_and_replay_exactly() {
+        let first = run_once();
+        let kinds: Vec<bool> = first.iter().map(PohEntry::is_tick).collect();
+        assert_eq!(kinds, vec![true, true, false, true]);
+        assert!(first.iter().all(|entry| entry.num_hashes == 8));
+
+        let second = run_once();
+        let hashes = |entries: &[PohEntry]| entries.iter().map(|entry| entry.hash).collect::<Vec<_>>();
+        assert_eq!(hashes(&first), hashes(&second));
+    }
+
+    #[test]
+    fn reset_continues_from_the_given_hash() {
+        let clock = mock_clock();
+        let service = start(&clock);
+        let head = Hash::digest(b"head");
+        let entries = service.reset(head);
+        let stamp = service.record(Hash::digest(b"tx1")).unwrap();
+        drop(service);
+
+        assert_eq!(entries.try_iter().map(|entry| entry.hash).collect::<Vec<_>>(), vec![stamp.hash]);
+        assert!(crate::poh::verifier::verify_entries(&head, std::slice::from_ref(&stamp)).is_ok());
+    }
+}
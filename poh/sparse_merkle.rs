@@ -0,0 +1,190 @@
+use crate::poh::hash::{Hash, HASH_BYTES};
+use crate::poh::merkle::{hash_leaf, hash_node};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// One level per bit of the key.
+pub const TREE_DEPTH: usize = HASH_BYTES * 8;
+
+// Hash of a subtree with no leaves in it, by height: an empty leaf is the
+// zero hash and each level above pairs two empty subtrees.
+fn empty_hashes() -> &'static [Hash] {
+    static EMPTY: OnceLock<Vec<Hash>> = OnceLock::new();
+    EMPTY.get_or_init(|| {
+        let mut hashes = vec![Hash::default()];
+        for height in 0..TREE_DEPTH {
+            hashes.push(hash_node(&hashes[height], &hashes[height]));
+        }
+        hashes
+    })
+}
+
+// Bit `depth` of the key, counting from the most significant bit, picks the
+// branch taken at that depth below the root: 0 goes left, 1 goes right.
+fn bit(key: &Hash, depth: usize) -> bool {
+    key.as_bytes()[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+// The key with every bit from `depth` on cleared: the path to the node at
+// that depth.
+fn prefix(key: &Hash, depth: usize) -> Hash {
+    let mut bytes = *key.as_bytes();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let start = i * 8;
+        if start >= depth {
+            *byte = 0;
+        } else if start + 8 > depth {
+            *byte &= 0xff << (start + 8 - depth);
+        }
+    }
+    Hash(bytes)
+}
+
+fn flip(key: &Hash, depth: usize) -> Hash {
+    let mut bytes = *key.as_bytes();
+    bytes[depth / 8] ^= 0x80 >> (depth % 8);
+    Hash(bytes)
+}
+
+// Merkle tree over all 2^256 keys, of which almost every leaf is empty. Only
+// nodes whose hash differs from the empty subtree at their height are stored,
+// keyed by (height, path), so an update rehashes just the one path from the
+// leaf to the root.
+#[derive(Debug, Clone, Default)]
+pub struct SparseMerkleTree {
+    nodes: HashMap<(usize, Hash), Hash>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        SparseMerkleTree::default()
+    }
+
+    pub fn root(&self) -> Hash {
+        self.node(TREE_DEPTH, &Hash::default())
+    }
+
+    fn node(&self, height: usize, path: &Hash) -> Hash {
+        self.nodes
+            .get(&(height, *path))
+            .copied()
+            .unwrap_or(empty_hashes()[height])
+    }
+
+    fn set_node(&mut self, height: usize, path: Hash, hash: Hash) {
+        if hash == empty_hashes()[height] {
+            self.nodes.remove(&(height, path));
+        } else {
+            self.nodes.insert((height, path), hash);
+        }
+    }
+
+    // Set the leaf at `key` to `value`, or clear it with `None`.
+    pub fn update(&mut self, key: &Hash, value: Option<&[u8]>) {
+        let mut current = value.map_or(Hash::default(), |value| leaf_hash(key, value));
+        self.set_node(0, *key, current);
+        for height in 0..TREE_DEPTH {
+            let depth = TREE_DEPTH - height - 1; // depth of the parent
+            let sibling = self.node(height, &prefix(&flip(key, depth), depth + 1));
+            current = if bit(key, depth) {
+                hash_node(&sibling, &current)
+            } else {
+                hash_node(&current, &sibling)
+            };
+            self.set_node(height + 1, prefix(key, depth), current);
+        }
+    }
+
+    // Siblings on the path from `key` to the root. Proves the leaf's value or,
+    // for an empty leaf, that the key is absent.
+    pub fn prove(&self, key: &Hash) -> SparseMerkleProof {
+        let siblings = (0..TREE_DEPTH)
+            .filter_map(|height| {
+                let depth = TREE_DEPTH - height - 1;
+                let sibling = self.node(height, &prefix(&flip(key, depth), depth + 1));
+                (sibling != empty_hashes()[height]).then_some((height, sibling))
+            })
+            .collect();
+        SparseMerkleProof { siblings }
+    }
+}
+
+fn leaf_hash(key: &Hash, value: &[u8]) -> Hash {
+    hash_leaf(&[key.as_ref(), value].concat())
+}
+
+// Only the siblings that are not empty subtrees are carried, tagged with
+// their height; the rest are implied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SparseMerkleProof {
+    pub siblings: Vec<(usize, Hash)>,
+}
+
+impl SparseMerkleProof {
+    pub fn compute_root(&self, key: &Hash, value: Option<&[u8]>) -> Hash {
+        let mut siblings = self.siblings.iter().peekable();
+        let mut current = value.map_or(Hash::default(), |value| leaf_hash(key, value));
+        for height in 0..TREE_DEPTH {
+            let sibling = match siblings.next_if(|(at, _)| *at == height) {
+                Some((_, hash)) => *hash,
+                None => empty_hashes()[height],
+            };
+            current = if bit(key, TREE_DEPTH - height - 1) {
+                hash_node(&sibling, &current)
+            } else {
+                hash_node(&current, &sibling)
+            };
+        }
+        current
+    }
+
+    pub fn verify(&self, key: &Hash, value: Option<&[u8]>, root: &Hash) -> bool {
+        self.compute_root(key, value) == *root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Hash {
+        Hash::digest(name.as_bytes())
+    }
+
+    #[test]
+    fn proves_values_and_absence() {
+        let mut tree = SparseMerkleTree::new();
+        tree.update(&key("alice"), Some(b"10"));
+        tree.update(&key("bob"), Some(b"20"));
+        let root = tree.root();
+
+        assert!(tree.prove(&key("alice")).verify(&key("alice"), Some(b"10"), &root));
+        assert!(tree.prove(&key("bob")).verify(&key("bob"), Some(b"20"), &root));
+        assert!(!tree.prove(&key("alice")).verify(&key("alice"), Some(b"11"), &root));
+        assert!(!tree.prove(&key("alice")).verify(&key("alice"), None, &root));
+
+        let absent = tree.prove(&key("carol"));
+        assert!(absent.verify(&key("carol"), None, &root));
+        assert!(!absent.verify(&key("carol"), Some(b"0"), &root));
+    }
+
+    #[test]
+    fn root_depends_only_on_contents() {
+        let mut forward = SparseMerkleTree::new();
+        let mut backward = SparseMerkleTree::new();
+        let names = ["a", "b", "c", "d"];
+        for name in names {
+            forward.update(&key(name), Some(name.as_bytes()));
+        }
+        for name in names.iter().rev() {
+            backward.update(&key(name), Some(name.as_bytes()));
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        for name in names {
+            forward.update(&key(name), None);
+        }
+        assert_eq!(forward.root(), SparseMerkleTree::new().root());
+    }
+}
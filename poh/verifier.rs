@@ -0,0 +1,224 @@
+use crate::poh::entry::{self, PohEntry};
+use crate::poh::hash::Hash;
+use crate::poh::merkle;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Why a single entry failed to verify against its predecessor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryFault {
+    ZeroHashes,
+    TooManyHashes { num_hashes: u64, max: u64 }, // more than an honest producer would hash between entries
+    TransactionsRootMismatch { expected: Hash, found: Hash },
+    HashMismatch { expected: Hash, found: Hash },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationFailure {
+    pub index: usize, // position of the first bad entry in the verified slice
+    pub fault: EntryFault,
+}
+
+impl PohEntry {
+    // Recompute this entry from the hash of the entry before it.
+    pub fn verify(&self, prev_hash: &Hash) -> Result<(), EntryFault> {
+        if self.num_hashes == 0 {
+            return Err(EntryFault::ZeroHashes);
+        }
+
+        let transactions_root = merkle::root(&self.transactions);
+        if transactions_root != self.transactions_root {
+            return Err(EntryFault::TransactionsRootMismatch {
+                expected: transactions_root,
+                found: self.transactions_root,
+            });
+        }
+
+        let state = entry::extend_hash(prev_hash, self.num_hashes - 1);
+        let expected = entry::record_hash(
+            &state,
+            &self.transactions,
+            &transactions_root,
+            &self.events,
+            &self.performance_digest,
+            self.timestamp,
+        );
+        if expected != self.hash {
+            return Err(EntryFault::HashMismatch {
+                expected,
+                found: self.hash,
+            });
+        }
+        Ok(())
+    }
+}
+
+// Walk `entries` in order starting from `start_hash` and stop at the first entry
+// that does not follow from its predecessor.
+pub fn verify_entries(start_hash: &Hash, entries: &[PohEntry]) -> Result<(), VerificationFailure> {
+    let mut prev_hash = start_hash;
+    for (index, entry) in entries.iter().enumerate() {
+        entry
+            .verify(prev_hash)
+            .map_err(|fault| VerificationFailure { index, fault })?;
+        prev_hash = &entry.hash;
+    }
+    Ok(())
+}
+
+// Refuse entries claiming more hashes than `max_hashes_per_entry` before any
+// of them is recomputed, so untrusted entries cannot demand unbounded work.
+pub fn check_hash_counts(entries: &[PohEntry], max_hashes_per_entry: u64) -> Result<(), VerificationFailure> {
+    match entries.iter().position(|entry| entry.num_hashes > max_hashes_per_entry) {
+        Some(index) => Err(VerificationFailure {
+            index,
+            fault: EntryFault::TooManyHashes {
+                num_hashes: entries[index].num_hashes,
+                max: max_hashes_per_entry,
+            },
+        }),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParallelVerification {
+    pub result: Result<(), VerificationFailure>,
+    pub entries_verified: usize,
+    pub hashes_verified: u64,
+    pub elapsed: Duration,
+    pub hashes_per_second: f64,
+}
+
+// Split `entries` into one segment per thread and verify the segments
+// concurrently. Every entry carries its own hash, so a segment can start from
+// the hash of the entry just before it without waiting on the other threads.
+// `num_threads` of 0 uses all available cores.
+pub fn verify_entries_parallel(
+    start_hash: &Hash,
+    entries: &[PohEntry],
+    num_threads: usize,
+) -> ParallelVerification {
+    let started = Instant::now();
+    let num_threads = if num_threads == 0 {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    } else {
+        num_threads
+    };
+    let segment_len = entries.len().div_ceil(num_threads).max(1);
+
+    let failures: Vec<VerificationFailure> = thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .chunks(segment_len)
+            .enumerate()
+            .map(|(segment, chunk)| {
+                let offset = segment * segment_len;
+                let prev_hash = if offset == 0 {
+                    start_hash
+                } else {
+                    &entries[offset - 1].hash
+                };
+                scope.spawn(move || {
+                    verify_entries(prev_hash, chunk).map_err(|failure| VerificationFailure {
+                        index: failure.index + offset,
+                        fault: failure.fault,
+                    })
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().expect("PoH verification thread panicked").err())
+            .collect()
+    });
+
+    let result = match failures.into_iter().min_by_key(|failure| failure.index) {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    };
+
+    let elapsed = started.elapsed();
+    let hashes_verified: u64 = entries.iter().map(|entry| entry.num_hashes).sum();
+    let hashes_per_second = if elapsed.as_secs_f64() > 0.0 {
+        hashes_verified as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    };
+
+    ParallelVerification {
+        result,
+        entries_verified: entries.len(),
+        hashes_verified,
+        elapsed,
+        hashes_per_second,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poh::clock;
+    use crate::poh::config::PohConfig;
+    use crate::poh::generator::PohGenerator;
+
+    // Ticks and records alternating, `count` in all, from the default hash.
+    fn entries(count: usize) -> Vec<PohEntry> {
+        let config = PohConfig {
+            hashes_per_tick: 4,
+            ticks_per_slot: 2,
+            ..PohConfig::default()
+        };
+        let mut generator = PohGenerator::with_config(10, config, clock::system_clock());
+        (0..count)
+            .map(|index| match index % 2 {
+                0 => generator.tick(),
+                _ => generator.record(vec![format!("tx{}", index)], Hash::default()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_the_first_bad_entry_and_why() {
+        let mut chain = entries(6);
+        assert_eq!(verify_entries(&Hash::default(), &chain), Ok(()));
+
+        chain[3].transactions[0] = "forged".to_string();
+        chain[5].num_hashes = 0;
+        let failure = verify_entries(&Hash::default(), &chain).unwrap_err();
+        assert_eq!(failure.index, 3);
+        assert!(matches!(failure.fault, EntryFault::TransactionsRootMismatch { .. }));
+
+        let failure = verify_entries(&Hash::default(), &chain[4..]).unwrap_err();
+        assert_eq!(failure.index, 0);
+        assert!(matches!(failure.fault, EntryFault::HashMismatch { .. }));
+        assert_eq!(
+            verify_entries(&chain[4].hash, &chain[5..]),
+            Err(VerificationFailure {
+                index: 0,
+                fault: EntryFault::ZeroHashes
+            })
+        );
+    }
+
+    #[test]
+    fn parallel_segments_agree_with_sequential_verification() {
+        let mut chain = entries(9);
+        for threads in [1, 2, 4, 16] {
+            let verification = verify_entries_parallel(&Hash::default(), &chain, threads);
+            assert_eq!(verification.result, Ok(()));
+            assert_eq!(verification.entries_verified, 9);
+            assert_eq!(verification.hashes_verified, chain.iter().map(|entry| entry.num_hashes).sum::<u64>());
+        }
+
+        // failures in several segments: the earliest is reported, by its index in the whole run
+        chain[5].num_hashes += 1;
+        chain[7].transactions.clear();
+        let sequential = verify_entries(&Hash::default(), &chain);
+        for threads in [1, 2, 3, 4, 16] {
+            assert_eq!(verify_entries_parallel(&Hash::default(), &chain, threads).result, sequential);
+        }
+        assert_eq!(sequential.unwrap_err().index, 5);
+        assert_eq!(verify_entries_parallel(&Hash::default(), &[], 4).result, Ok(()));
+    }
+}
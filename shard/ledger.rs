@@ -0,0 +1,395 @@
+use crate::codec::codec::Encoder;
+use crate::poh::hash::Hash;
+use crate::poh::sparse_merkle::{SparseMerkleProof, SparseMerkleTree};
+use crate::shard::shard::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// Share of a block's fees that goes to its proposer; validators split the rest.
+pub const PROPOSER_FEE_PERCENT: u64 = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionError {
+    Overdraft { account: String, balance: u64, amount: u64 },
+    BalanceOverflow { account: String },
+    SupplyOverflow,
+    StaleNonce { account: String, expected: u64, found: u64 }, // already used or skipped past
+    NonceGap { account: String, expected: u64, found: u64 },   // earlier nonces still missing
+    NonceOverflow { account: String },                          // the sequence has run out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupplyViolation {
+    pub expected: u64,
+    pub found: u64,
+}
+
+// Account balances held by one shard. `supply` is what the balances must add
+// up to: genesis funding plus cross-shard transfers in, minus transfers out.
+//
+// `nonces` holds the next sequence number per sending account. An account's
+// own shard requires its transfers in exact nonce order; a receiving shard
+// only requires them to increase, since it sees just the transfers sent its
+// way. A receiving shard only takes transfers proven to be in a block the
+// sending shard announced to it, so a gap it sees was filled, in order, on
+// the sender's own shard as long as that shard's announcements are honest.
+// Either way a transfer can never be applied twice.
+//
+// Every account's balance and nonce are also kept in a sparse Merkle tree
+// keyed by the hash of the account name, updated as accounts change. Its root
+// is the state root committed to in blocks and checkpoints.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    balances: HashMap<String, u64>,
+    supply: u64,
+    nonces: HashMap<String, u64>,
+    tree: SparseMerkleTree,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Ledger::default()
+    }
+
+    pub fn from_snapshot(balances: HashMap<String, u64>, supply: u64, nonces: HashMap<String, u64>) -> Self {
+        let mut ledger = Ledger {
+            balances,
+            supply,
+            nonces,
+            tree: SparseMerkleTree::new(),
+        };
+        let accounts: Vec<String> = ledger.balances.keys().chain(ledger.nonces.keys()).cloned().collect();
+        for account in accounts {
+            ledger.update_tree(&account);
+        }
+        ledger
+    }
+
+    pub fn balances(&self) -> &HashMap<String, u64> {
+        &self.balances
+    }
+
+    pub fn balance(&self, account: &str) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    pub fn supply(&self) -> u64 {
+        self.supply
+    }
+
+    pub fn nonces(&self) -> &HashMap<String, u64> {
+        &self.nonces
+    }
+
+    pub fn next_nonce(&self, account: &str) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+
+    // Whether `tx` is next in its sender's sequence as this shard sees it.
+    pub fn check_nonce(&self, tx: &Transaction, shard_id: usize) -> Result<(), ExecutionError> {
+        let expected = self.next_nonce(&tx.from_account);
+        if tx.nonce < expected {
+            return Err(ExecutionError::StaleNonce {
+                account: tx.from_account.clone(),
+                expected,
+                found: tx.nonce,
+            });
+        }
+        if tx.from_shard == shard_id && tx.nonce > expected {
+            return Err(ExecutionError::NonceGap {
+                account: tx.from_account.clone(),
+                expected,
+                found: tx.nonce,
+            });
+        }
+        Ok(())
+    }
+
+    // Create funds out of nothing; only for genesis allocations.
+    pub fn mint(&mut self, account: &str, amount: u64) -> Result<(), ExecutionError> {
+        self.supply = self.supply.checked_add(amount).ok_or(ExecutionError::SupplyOverflow)?;
+        let balance = self.balances.entry(account.to_string()).or_insert(0);
+        *balance = balance.checked_add(amount).ok_or(ExecutionError::BalanceOverflow {
+            account: account.to_string(),
+        })?;
+        self.update_tree(account);
+        Ok(())
+    }
+
+    // Apply this shard's side of a transfer: the sender is debited on the
+    // sending shard and the recipient credited on the receiving shard, so an
+    // intra-shard transfer does both. The sending shard also takes the fee
+    // from the sender and returns it, for `pay_fees` to hand out once the
+    // block's transfers are done. Nothing changes if the transfer fails.
+    pub fn apply(&mut self, tx: &Transaction, shard_id: usize) -> Result<u64, ExecutionError> {
+        self.check_nonce(tx, shard_id)?;
+        let next_nonce = tx.nonce.checked_add(1).ok_or_else(|| ExecutionError::NonceOverflow {
+            account: tx.from_account.clone(),
+        })?;
+        let debit = tx.from_shard == shard_id;
+        let credit = tx.to_shard == shard_id;
+
+        let mut supply = self.supply;
+        let mut from_balance = None;
+        if debit {
+            let balance = self.balance(&tx.from_account);
+            let cost = tx.amount.saturating_add(tx.fee);
+            let remaining = balance.checked_sub(cost).ok_or_else(|| ExecutionError::Overdraft {
+                account: tx.from_account.clone(),
+                balance,
+                amount: cost,
+            })?;
+            from_balance = Some(remaining);
+            if !credit {
+                supply -= tx.amount;
+            }
+        }
+
+        let mut to_balance = None;
+        if credit {
+            // on an intra-shard self-transfer the credit lands on the debited balance
+            let balance = match from_balance {
+                Some(remaining) if tx.from_account == tx.to_account => remaining,
+                _ => self.balance(&tx.to_account),
+            };
+            to_balance = Some(balance.checked_add(tx.amount).ok_or_else(|| ExecutionError::BalanceOverflow {
+                account: tx.to_account.clone(),
+            })?);
+            if !debit {
+                supply = supply.checked_add(tx.amount).ok_or(ExecutionError::SupplyOverflow)?;
+            }
+        }
+
+        if let Some(balance) = from_balance {
+            self.balances.insert(tx.from_account.clone(), balance);
+        }
+        if let Some(balance) = to_balance {
+            self.balances.insert(tx.to_account.clone(), balance);
+        }
+        self.supply = supply;
+        self.nonces.insert(tx.from_account.clone(), next_nonce);
+        self.update_tree(&tx.from_account);
+        if credit {
+            self.update_tree(&tx.to_account);
+        }
+        Ok(if debit { tx.fee } else { 0 })
+    }
+
+    // Credit fees collected by `apply`: `PROPOSER_FEE_PERCENT` to the
+    // proposer and the rest split evenly across `validators`, with any
+    // remainder going to the proposer. Whoever is missing, their share goes
+    // to the others; with nobody to pay, the fees are burned.
+    pub fn pay_fees(&mut self, fees: u64, proposer: Option<&str>, validators: &[&str]) -> Result<(), ExecutionError> {
+        let proposer_share = match (proposer, validators.is_empty()) {
+            (None, true) => {
+                self.supply -= fees;
+                return Ok(());
+            }
+            (None, false) => 0,
+            (Some(_), true) => fees,
+            (Some(_), false) => (fees as u128 * PROPOSER_FEE_PERCENT as u128 / 100) as u64,
+        };
+
+        // the proposer may also be one of the validators, so shares are summed per account
+        let mut payouts: BTreeMap<&str, u64> = BTreeMap::new();
+        let validator_pool = fees - proposer_share;
+        let mut remainder = validator_pool;
+        if !validators.is_empty() {
+            let each = validator_pool / validators.len() as u64;
+            remainder -= each * validators.len() as u64;
+            for validator in validators {
+                *payouts.entry(validator).or_insert(0) += each;
+            }
+        }
+        *payouts.entry(proposer.unwrap_or_else(|| validators[0])).or_insert(0) += proposer_share + remainder;
+
+        let mut credited = Vec::with_capacity(payouts.len());
+        for (account, amount) in payouts.into_iter().filter(|(_, amount)| *amount > 0) {
+            let balance = self.balance(account).checked_add(amount).ok_or_else(|| ExecutionError::BalanceOverflow {
+                account: account.to_string(),
+            })?;
+            credited.push((account, balance));
+        }
+        for (account, balance) in credited {
+            self.balances.insert(account.to_string(), balance);
+            self.update_tree(account);
+        }
+        Ok(())
+    }
+
+    pub fn total_balance(&self) -> u128 {
+        self.balances.values().map(|balance| *balance as u128).sum()
+    }
+
+    pub fn check_supply(&self) -> Result<(), SupplyViolation> {
+        let total = self.total_balance();
+        if total != self.supply as u128 {
+            return Err(SupplyViolation {
+                expected: self.supply,
+                found: total.min(u64::MAX as u128) as u64,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn state_root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    // Proof of `account`'s balance and nonce under the current state root. An
+    // account the ledger has never seen is proven to hold nothing.
+    pub fn prove_balance(&self, account: &str) -> BalanceProof {
+        BalanceProof {
+            account: account.to_string(),
+            balance: self.balance(account),
+            nonce: self.next_nonce(account),
+            proof: self.tree.prove(&account_key(account)),
+        }
+    }
+
+    fn update_tree(&mut self, account: &str) {
+        let value = account_value(self.balance(account), self.next_nonce(account));
+        self.tree.update(&account_key(account), value.as_deref());
+    }
+}
+
+fn account_key(account: &str) -> Hash {
+    Hash::digest(account.as_bytes())
+}
+
+// An account with no funds and no transfers is an empty leaf, so it proves
+// the same way as one that was never created.
+fn account_value(balance: u64, nonce: u64) -> Option<Vec<u8>> {
+    if balance == 0 && nonce == 0 {
+        return None;
+    }
+    let mut encoder = Encoder::new();
+    encoder.put_u64(balance);
+    encoder.put_u64(nonce);
+    Some(encoder.finish())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceProof {
+    pub account: String,
+    pub balance: u64,
+    pub nonce: u64,
+    pub proof: SparseMerkleProof,
+}
+
+impl BalanceProof {
+    pub fn verify(&self, state_root: &Hash) -> bool {
+        let value = account_value(self.balance, self.nonce);
+        self.proof.verify(&account_key(&self.account), value.as_deref(), state_root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shard::shard::TransactionStatus;
+
+    fn transfer(to: &str, amount: u64, fee: u64, nonce: u64, to_shard: usize) -> Transaction {
+        Transaction {
+            id: format!("alice-{}", nonce),
+            from_account: "alice".to_string(),
+            to_account: to.to_string(),
+            amount,
+            fee,
+            nonce,
+            from_shard: 1,
+            to_shard,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Pending,
+        }
+    }
+
+    fn funded() -> Ledger {
+        let mut ledger = Ledger::new();
+        ledger.mint("alice", 1_000).unwrap();
+        ledger.mint("bob", 500).unwrap();
+        ledger
+    }
+
+    #[test]
+    fn transfers_and_fees_conserve_supply() {
+        let mut ledger = funded();
+        let mut fees = 0;
+        for nonce in 0..5 {
+            fees += ledger.apply(&transfer("bob", 100, 3, nonce, 1), 1).unwrap();
+        }
+        ledger.pay_fees(fees, Some("proposer"), &["v1", "v2"]).unwrap();
+
+        assert_eq!(ledger.supply(), 1_500);
+        assert_eq!(ledger.check_supply(), Ok(()));
+        assert_eq!(ledger.balance("alice"), 1_000 - 5 * 103);
+        assert_eq!(ledger.balance("bob"), 1_000);
+    }
+
+    #[test]
+    fn cross_shard_transfer_moves_supply_between_shards() {
+        let mut sender = funded();
+        let mut receiver = Ledger::new();
+        receiver.mint("carol", 200).unwrap();
+        let tx = transfer("carol", 300, 5, 0, 2);
+
+        let fee = sender.apply(&tx, 1).unwrap();
+        sender.pay_fees(fee, Some("proposer"), &[]).unwrap();
+        assert_eq!(receiver.apply(&tx, 2), Ok(0));
+
+        assert_eq!(sender.check_supply(), Ok(()));
+        assert_eq!(receiver.check_supply(), Ok(()));
+        assert_eq!(sender.supply() + receiver.supply(), 1_500 + 200);
+        assert_eq!(receiver.balance("carol"), 500);
+    }
+
+    #[test]
+    fn failed_transfers_change_nothing() {
+        let mut ledger = funded();
+        let root = ledger.state_root();
+
+        assert!(matches!(
+            ledger.apply(&transfer("bob", 2_000, 0, 0, 1), 1),
+            Err(ExecutionError::Overdraft { .. })
+        ));
+        assert!(matches!(
+            ledger.apply(&transfer("bob", 1, 0, 1, 1), 1),
+            Err(ExecutionError::NonceGap { .. })
+        ));
+        assert_eq!(ledger.state_root(), root);
+
+        ledger.apply(&transfer("bob", 1, 0, 0, 1), 1).unwrap();
+        assert!(matches!(
+            ledger.apply(&transfer("bob", 1, 0, 0, 1), 1),
+            Err(ExecutionError::StaleNonce { .. })
+        ));
+        assert_eq!(ledger.check_supply(), Ok(()));
+    }
+
+    #[test]
+    fn a_receiving_shard_takes_increasing_nonces_only() {
+        let mut receiver = Ledger::new();
+        receiver.apply(&transfer("carol", 10, 0, 3, 2), 2).unwrap();
+        receiver.apply(&transfer("carol", 10, 0, 7, 2), 2).unwrap();
+        assert!(matches!(
+            receiver.apply(&transfer("carol", 10, 0, 7, 2), 2),
+            Err(ExecutionError::StaleNonce { .. })
+        ));
+        assert!(matches!(
+            receiver.apply(&transfer("carol", 10, 0, 5, 2), 2),
+            Err(ExecutionError::StaleNonce { .. })
+        ));
+        assert_eq!(receiver.balance("carol"), 20);
+        assert_eq!(receiver.check_supply(), Ok(()));
+    }
+
+    #[test]
+    fn a_lone_proposer_takes_every_fee() {
+        let mut ledger = funded();
+        let fee = ledger.apply(&transfer("bob", 100, 7, 0, 1), 1).unwrap();
+        ledger.pay_fees(fee, Some("proposer"), &[]).unwrap();
+        assert_eq!(ledger.balance("proposer"), 7);
+        assert_eq!(ledger.check_supply(), Ok(()));
+    }
+}
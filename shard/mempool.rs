@@ -0,0 +1,363 @@
+use crate::shard::shard::Transaction;
+use crate::shard::validation::RejectionReason;
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
+pub const DEFAULT_MEMPOOL_MAX_TRANSACTIONS: usize = 20_000;
+pub const DEFAULT_MEMPOOL_MAX_BYTES: usize = 8 * 1024 * 1024;
+pub const DEFAULT_MEMPOOL_TTL: Duration = Duration::from_secs(300);
+pub const DEFAULT_MAX_OUTBOUND: usize = 20_000;
+pub const DEFAULT_MEMPOOL_MAX_CREDITS: usize = 20_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MempoolConfig {
+    pub max_transactions: usize,
+    pub max_bytes: usize,
+    pub ttl: Duration, // how long a transaction may wait before it expires
+    pub max_outbound: usize, // cross-shard transfers waiting for gossip before blocks stop adding more
+    pub max_credits: usize,  // credits waiting for a block before more are held back at the sender
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            max_transactions: DEFAULT_MEMPOOL_MAX_TRANSACTIONS,
+            max_bytes: DEFAULT_MEMPOOL_MAX_BYTES,
+            ttl: DEFAULT_MEMPOOL_TTL,
+            max_outbound: DEFAULT_MAX_OUTBOUND,
+            max_credits: DEFAULT_MEMPOOL_MAX_CREDITS,
+        }
+    }
+}
+
+// Why a transaction was not taken into the pool. Only `PoolFull` is worth
+// retrying as is; the others will be refused again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdmissionError {
+    PoolFull,
+    Duplicate,
+    Rejected(RejectionReason),
+}
+
+impl AdmissionError {
+    pub fn is_retryable(&self) -> bool {
+        *self == AdmissionError::PoolFull
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PooledTransaction {
+    tx: Transaction,
+    size: usize,
+    admitted_at: DateTime<Utc>,
+}
+
+// Fee per byte, scaled up so it can be compared as an integer.
+fn fee_rate(tx: &Transaction, size: usize) -> u128 {
+    tx.fee as u128 * 1_000_000 / size.max(1) as u128
+}
+
+// Orders the pool from least to most deserving: lower fee rate first, and
+// among equals the later arrival first.
+type PriorityKey = (u128, Reverse<u64>);
+
+// Transactions waiting for a block, capped by count and by encoded size.
+// Entries are kept in arrival order and indexed by id, so a transaction can
+// only be pooled once. When the pool is full, a newcomer paying more per byte
+// than the cheapest entry takes its place; otherwise it is turned away.
+//
+// Credits for transfers another shard has already debited wait in a queue of
+// their own. Only credits whose debit proof has been checked belong there, and
+// only one per sending account and nonce. Dropping one would destroy the
+// funds, so they are never evicted or expired and go into blocks first, in
+// arrival order, whatever they pay. Instead the queue is capped: once it is
+// full, or credits and entries together reach the byte limit, new credits are
+// turned away as `PoolFull` and wait at the sending end to be offered again.
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: BTreeMap<u64, PooledTransaction>, // by arrival sequence
+    credits: BTreeMap<u64, Transaction>,       // by arrival sequence
+    ids: HashMap<String, u64>,                 // entries and credits alike
+    credit_nonces: HashSet<(String, u64)>,     // sending account and nonce of each credit
+    priority: BTreeSet<PriorityKey>,
+    next_seq: u64,
+    bytes: usize,
+    credit_bytes: usize,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Mempool {
+            config,
+            entries: BTreeMap::new(),
+            credits: BTreeMap::new(),
+            ids: HashMap::new(),
+            credit_nonces: HashSet::new(),
+            priority: BTreeSet::new(),
+            next_seq: 0,
+            bytes: 0,
+            credit_bytes: 0,
+        }
+    }
+
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.credits.len()
+    }
+
+    pub fn credit_count(&self) -> usize {
+        self.credits.len()
+    }
+
+    // Encoded size of the entries and credits.
+    pub fn bytes(&self) -> usize {
+        self.bytes + self.credit_bytes
+    }
+
+    pub fn contains(&self, tx_id: &str) -> bool {
+        self.ids.contains_key(tx_id)
+    }
+
+    pub fn get(&self, tx_id: &str) -> Option<&Transaction> {
+        let seq = self.ids.get(tx_id)?;
+        self.credits.get(seq).or_else(|| self.entries.get(seq).map(|entry| &entry.tx))
+    }
+
+    // Credits, then the other entries, each in arrival order.
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.credits.values().chain(self.entries.values().map(|entry| &entry.tx))
+    }
+
+    // Queue a credit for a transfer whose debit on another shard has been
+    // proven. A second credit for the same sending account and nonce is a
+    // duplicate, whatever its id.
+    pub fn insert_credit(&mut self, tx: Transaction) -> Result<(), AdmissionError> {
+        if self.contains(&tx.id) || self.credit_nonces.contains(&(tx.from_account.clone(), tx.nonce)) {
+            return Err(AdmissionError::Duplicate);
+        }
+        if self.credits.len() >= self.config.max_credits || self.bytes() + tx.size() > self.config.max_bytes {
+            return Err(AdmissionError::PoolFull);
+        }
+        self.push_credit(tx);
+        Ok(())
+    }
+
+    // Put back a credit that was queued before, after its block was orphaned
+    // or on a restart. It was within the limits when it first came in and
+    // cannot be dropped now, so only a duplicate is refused.
+    pub fn readmit_credit(&mut self, tx: Transaction) -> Result<(), AdmissionError> {
+        if self.contains(&tx.id) || self.credit_nonces.contains(&(tx.from_account.clone(), tx.nonce)) {
+            return Err(AdmissionError::Duplicate);
+        }
+        self.push_credit(tx);
+        Ok(())
+    }
+
+    fn push_credit(&mut self, tx: Transaction) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.ids.insert(tx.id.clone(), seq);
+        self.credit_nonces.insert((tx.from_account.clone(), tx.nonce));
+        self.credit_bytes += tx.size();
+        self.credits.insert(seq, tx);
+    }
+
+    // Add `tx`, evicting cheaper transactions if that is what it takes to make
+    // room. Returns whatever was evicted.
+    pub fn insert(&mut self, tx: Transaction, now: DateTime<Utc>) -> Result<Vec<Transaction>, AdmissionError> {
+        if self.contains(&tx.id) {
+            return Err(AdmissionError::Duplicate);
+        }
+        let size = tx.size();
+        if size > self.config.max_bytes {
+            return Err(AdmissionError::Rejected(RejectionReason::TooLarge {
+                size,
+                max: self.config.max_bytes,
+            }));
+        }
+
+        let rate = fee_rate(&tx, size);
+        let fits = |count: usize, bytes: usize| count < self.config.max_transactions && bytes + size <= self.config.max_bytes;
+        let mut victims = Vec::new();
+        let (mut count, mut bytes) = (self.entries.len(), self.bytes());
+        for &(victim_rate, Reverse(seq)) in &self.priority {
+            if fits(count, bytes) {
+                break;
+            }
+            if victim_rate >= rate {
+                return Err(AdmissionError::PoolFull);
+            }
+            victims.push(seq);
+            count -= 1;
+            bytes -= self.entries[&seq].size;
+        }
+        if !fits(count, bytes) {
+            return Err(AdmissionError::PoolFull);
+        }
+
+        let evicted = victims.into_iter().filter_map(|seq| self.remove_seq(seq)).collect();
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.ids.insert(tx.id.clone(), seq);
+        self.bytes += size;
+        self.priority.insert((rate, Reverse(seq)));
+        self.entries.insert(seq, PooledTransaction { tx, size, admitted_at: now });
+        Ok(evicted)
+    }
+
+    fn remove_seq(&mut self, seq: u64) -> Option<Transaction> {
+        if let Some(tx) = self.credits.remove(&seq) {
+            self.ids.remove(&tx.id);
+            self.credit_nonces.remove(&(tx.from_account.clone(), tx.nonce));
+            self.credit_bytes -= tx.size();
+            return Some(tx);
+        }
+        let entry = self.entries.remove(&seq)?;
+        self.ids.remove(&entry.tx.id);
+        self.priority.remove(&(fee_rate(&entry.tx, entry.size), Reverse(seq)));
+        self.bytes -= entry.size;
+        Some(entry.tx)
+    }
+
+    pub fn remove(&mut self, tx_id: &str) -> Option<Transaction> {
+        let seq = *self.ids.get(tx_id)?;
+        self.remove_seq(seq)
+    }
+
+    // Drop every entry that has waited longer than the TTL. Entries are in
+    // arrival order, so the scan stops at the first one still in time.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<Transaction> {
+        let ttl = self.config.ttl;
+        let expired: Vec<u64> = self
+            .entries
+            .iter()
+            .take_while(|(_, entry)| {
+                // a negative wait, from a clock that stepped back, is never expired
+                now.signed_duration_since(entry.admitted_at)
+                    .to_std()
+                    .is_ok_and(|waited| waited > ttl)
+            })
+            .map(|(seq, _)| *seq)
+            .collect();
+        expired.into_iter().filter_map(|seq| self.remove_seq(seq)).collect()
+    }
+
+    // Up to `max` transactions: credits first, then the entries with the
+    // highest fee per byte first and in arrival order among equals. They
+    // stay in the pool until removed.
+    pub fn by_priority(&self, max: usize) -> Vec<Transaction> {
+        let ranked = self.priority.iter().rev().map(|(_, Reverse(seq))| &self.entries[seq].tx);
+        self.credits.values().chain(ranked).take(max).cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.credits.clear();
+        self.ids.clear();
+        self.credit_nonces.clear();
+        self.priority.clear();
+        self.bytes = 0;
+        self.credit_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shard::shard::TransactionStatus;
+
+    fn tx(id: &str, fee: u64, nonce: u64, from_shard: usize) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            from_account: "alice".to_string(),
+            to_account: "bob".to_string(),
+            amount: 1,
+            fee,
+            nonce,
+            from_shard,
+            to_shard: 1,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Pending,
+        }
+    }
+
+    fn pool(max_transactions: usize, max_credits: usize) -> Mempool {
+        Mempool::new(MempoolConfig {
+            max_transactions,
+            max_credits,
+            ..MempoolConfig::default()
+        })
+    }
+
+    fn ids(transactions: &[Transaction]) -> Vec<&str> {
+        transactions.iter().map(|tx| tx.id.as_str()).collect()
+    }
+
+    #[test]
+    fn evicts_the_cheapest_latest_arrival() {
+        let mut pool = pool(3, 3);
+        let now = Utc::now();
+        for (id, fee) in [("t1", 1), ("t2", 1), ("t3", 5)] {
+            assert!(pool.insert(tx(id, fee, 0, 1), now).unwrap().is_empty());
+        }
+
+        let evicted = pool.insert(tx("t4", 2, 0, 1), now).unwrap();
+        assert_eq!(ids(&evicted), ["t2"]);
+        assert_eq!(ids(&pool.by_priority(10)), ["t3", "t4", "t1"]);
+
+        // paying no more than the cheapest entry is not enough to get in
+        assert_eq!(pool.insert(tx("t5", 1, 0, 1), now).err(), Some(AdmissionError::PoolFull));
+        assert_eq!(pool.insert(tx("t1", 9, 0, 1), now).err(), Some(AdmissionError::Duplicate));
+        assert_eq!(pool.len(), 3);
+    }
+
+    #[test]
+    fn credits_are_never_evicted_or_expired() {
+        let mut pool = pool(1, 3);
+        let admitted = Utc::now();
+        for (nonce, id) in ["c1", "c2", "c3"].into_iter().enumerate() {
+            assert_eq!(pool.insert_credit(tx(id, 0, nonce as u64, 2)), Ok(()));
+        }
+        assert!(pool.insert(tx("t1", 1, 0, 1), admitted).unwrap().is_empty());
+        assert_eq!(ids(&pool.insert(tx("t2", 9, 1, 1), admitted).unwrap()), ["t1"]);
+        assert_eq!(pool.credit_count(), 3);
+
+        // credits go first, whatever they pay
+        assert_eq!(ids(&pool.by_priority(10)), ["c1", "c2", "c3", "t2"]);
+
+        let later = admitted + chrono::Duration::from_std(pool.config().ttl * 2).unwrap();
+        assert_eq!(ids(&pool.expire(later)), ["t2"]);
+        assert_eq!(ids(&pool.by_priority(10)), ["c1", "c2", "c3"]);
+    }
+
+    #[test]
+    fn credits_are_capped_and_counted() {
+        let mut pool = pool(10, 2);
+        let credit = tx("c1", 0, 4, 2);
+        let size = credit.size();
+        assert_eq!(pool.insert_credit(credit), Ok(()));
+        assert_eq!(pool.bytes(), size);
+
+        // one credit per sending account and nonce, whatever the id
+        assert_eq!(pool.insert_credit(tx("c1-again", 0, 4, 2)), Err(AdmissionError::Duplicate));
+        assert_eq!(pool.insert_credit(tx("c2", 0, 5, 2)), Ok(()));
+        assert_eq!(pool.insert_credit(tx("c3", 0, 6, 2)), Err(AdmissionError::PoolFull));
+
+        // a credit coming back after a reorg is taken even so
+        assert_eq!(pool.readmit_credit(tx("c3", 0, 6, 2)), Ok(()));
+        assert_eq!(pool.credit_count(), 3);
+
+        pool.remove("c1");
+        assert_eq!(pool.insert_credit(tx("c1-again", 0, 4, 2)), Err(AdmissionError::PoolFull));
+        pool.remove("c2");
+        assert_eq!(pool.insert_credit(tx("c1-again", 0, 4, 2)), Ok(()));
+        assert_eq!(pool.bytes(), pool.iter().map(Transaction::size).sum::<usize>());
+    }
+}
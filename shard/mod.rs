@@ -1 +1,8 @@
+pub mod ledger;
+pub mod mempool;
+pub mod remote_chains;
+#[allow(clippy::module_inception)]
 pub mod shard;
+pub mod status;
+pub mod tx_index;
+pub mod validation;
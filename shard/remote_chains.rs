@@ -0,0 +1,51 @@
+use crate::block::block::BlockHeader;
+use crate::block::verifier::ChainTip;
+use crate::poh::hash::Hash;
+use std::collections::HashMap;
+
+// Blocks of other shards that this shard trusts, by shard. A debit proof only
+// counts against one of these, never against the header the proof carries.
+//
+// A shard's blocks are trusted as its headers arrive from it, one at a time,
+// each extending a block already trusted: its genesis, or the block a
+// checkpoint of that shard settles. A checkpoint also drops the blocks below
+// it, so only the unsettled part of each chain is held.
+#[derive(Debug, Default)]
+pub struct RemoteChains {
+    chains: HashMap<usize, HashMap<Hash, u64>>, // block hash -> block number, per shard
+}
+
+impl RemoteChains {
+    pub fn new() -> Self {
+        RemoteChains::default()
+    }
+
+    // Trust the block `header` describes if it extends a trusted block of
+    // its shard. Returns whether it is trusted now.
+    pub fn add_header(&mut self, header: &BlockHeader) -> bool {
+        let genesis = ChainTip::default();
+        let chain = self.chains.entry(header.shard_id).or_default();
+        let extends = match chain.get(&header.previous_hash) {
+            Some(&parent) => header.block_number == parent + 1,
+            None => header.previous_hash == genesis.block_hash && header.block_number == genesis.block_number + 1,
+        };
+        if extends {
+            chain.insert(header.hash(), header.block_number);
+        }
+        extends
+    }
+
+    // Trust the block `tip` of `shard_id`, settled by a checkpoint, and
+    // forget that shard's blocks below it.
+    pub fn add_checkpoint(&mut self, shard_id: usize, tip: &ChainTip) {
+        let chain = self.chains.entry(shard_id).or_default();
+        chain.retain(|_, block_number| *block_number >= tip.block_number);
+        chain.insert(tip.block_hash, tip.block_number);
+    }
+
+    pub fn contains(&self, shard_id: usize, block_hash: &Hash) -> bool {
+        self.chains
+            .get(&shard_id)
+            .is_some_and(|chain| chain.contains_key(block_hash))
+    }
+}
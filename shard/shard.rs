@@ -1,17 +1,50 @@
+use crate::poh::clock::{self, SharedClock};
+use crate::poh::config::PohConfig;
+use crate::poh::elapsed::ElapsedProof;
+use crate::poh::entry::PohEntry;
 use crate::poh::generator::PohGenerator;
-use crate::block::block::Block;
+use crate::poh::service::PohService;
+use crate::poh::hash::Hash;
+use crate::block::block::{Block, BlockHeader, InclusionProof};
+use crate::block::tree::{BlockTree, ForkChoice};
+use crate::block::verifier::{self, BlockError, BlockRules, ChainTip};
+use crate::codec::codec::{Decode, DecodeError, Decoder, Encode, Encoder};
+use crate::crypto::keys::{self, Keypair, PublicKey, Signature, SignatureError};
+use crate::shard::ledger::{BalanceProof, ExecutionError, Ledger, SupplyViolation};
+use crate::shard::mempool::{AdmissionError, Mempool, MempoolConfig};
+use crate::shard::remote_chains::RemoteChains;
+use crate::shard::status::{BlockRef, FailedTransactions, StatusChange, StatusIndex, StatusReason};
+use crate::shard::tx_index::{DuplicateTransaction, TransactionIndex, TransactionLocation, TransactionReceipt};
+use crate::shard::validation::{self, RejectionReason, TransactionValidator, ValidationContext, ValidationPipeline};
+use crate::storage::block_store::BlockStore;
+use crate::storage::snapshot_store::SnapshotStore;
 use crate::validator::validator::{Validator, ValidatorPerformance};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
 use std::time::{Duration, Instant};
 use crate::BLOCK_GEN_TIMES;
 use crate::LAST_BLOCK_TIMESTAMP;
 use chrono::Utc;
 
+// Blocks this far under the tip are final. The newest of them is where the
+// shard snapshots its state and settles its chain, so no reorg reaches back
+// past it.
+pub const FINALITY_DEPTH: usize = 32;
+
 #[derive(Debug, Clone)]
 pub struct Checkpoint {
     pub shard_id: usize,
     pub block_height: u64,
+    pub block_hash: Hash, // hash of the block at `block_height`
+    pub poh_head: Hash,   // PoH hash the next block continues from
     pub ledger_snapshot: HashMap<String, u64>,
+    pub total_supply: u64, // what `ledger_snapshot` must add up to
+    pub nonce_snapshot: HashMap<String, u64>,
+    pub state_root: Hash, // root of the ledger's state tree, as in the block at `block_height`
     pub transaction_pool_snapshot: Vec<Transaction>,
     pub processed_transactions_snapshot: HashSet<String>,
 }
@@ -19,172 +52,870 @@ pub struct Checkpoint {
 impl Checkpoint {
     pub fn new(
         shard_id: usize,
-        block_height: u64,
-        ledger_snapshot: HashMap<String, u64>,
+        tip: ChainTip,
+        ledger: &Ledger,
         transaction_pool_snapshot: Vec<Transaction>,
         processed_transactions_snapshot: HashSet<String>,
     ) -> Self {
         Checkpoint {
             shard_id,
-            block_height,
-            ledger_snapshot,
+            block_height: tip.block_number,
+            block_hash: tip.block_hash,
+            poh_head: tip.poh_end_hash,
+            ledger_snapshot: ledger.balances().clone(),
+            total_supply: ledger.supply(),
+            nonce_snapshot: ledger.nonces().clone(),
+            state_root: ledger.state_root(),
             transaction_pool_snapshot,
             processed_transactions_snapshot,
         }
     }
+
+    pub fn ledger(&self) -> Ledger {
+        Ledger::from_snapshot(self.ledger_snapshot.clone(), self.total_supply, self.nonce_snapshot.clone())
+    }
+
+    pub fn tip(&self) -> ChainTip {
+        ChainTip {
+            block_number: self.block_height,
+            block_hash: self.block_hash,
+            poh_end_hash: self.poh_head,
+        }
+    }
+}
+
+// Maps and sets are written sorted by key so every node produces the same bytes.
+impl Encode for Checkpoint {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_usize(self.shard_id);
+        encoder.put_u64(self.block_height);
+        encoder.put_hash(&self.block_hash);
+        encoder.put_hash(&self.poh_head);
+
+        put_account_map(encoder, &self.ledger_snapshot);
+        encoder.put_u64(self.total_supply);
+        put_account_map(encoder, &self.nonce_snapshot);
+        encoder.put_hash(&self.state_root);
+
+        encoder.put_seq(self.transaction_pool_snapshot.iter());
+
+        let mut processed: Vec<&String> = self.processed_transactions_snapshot.iter().collect();
+        processed.sort();
+        encoder.put_seq(processed.into_iter());
+    }
+}
+
+impl Decode for Checkpoint {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let shard_id = decoder.usize()?;
+        let tip = ChainTip {
+            block_number: decoder.u64()?,
+            block_hash: decoder.hash()?,
+            poh_end_hash: decoder.hash()?,
+        };
+
+        let ledger = Ledger::from_snapshot(account_map(decoder)?, decoder.u64()?, account_map(decoder)?);
+        if decoder.hash()? != ledger.state_root() {
+            return Err(DecodeError::InvalidValue("ledger does not match the checkpoint's state root"));
+        }
+
+        let transaction_pool_snapshot = decoder.seq()?;
+        let processed_transactions_snapshot = decoder.seq::<String>()?.into_iter().collect();
+
+        Ok(Checkpoint::new(
+            shard_id,
+            tip,
+            &ledger,
+            transaction_pool_snapshot,
+            processed_transactions_snapshot,
+        ))
+    }
+}
+
+fn put_account_map(encoder: &mut Encoder, map: &HashMap<String, u64>) {
+    let mut accounts: Vec<(&String, &u64)> = map.iter().collect();
+    accounts.sort();
+    encoder.put_usize(accounts.len());
+    for (account, value) in accounts {
+        encoder.put_str(account);
+        encoder.put_u64(*value);
+    }
+}
+
+fn account_map(decoder: &mut Decoder) -> Result<HashMap<String, u64>, DecodeError> {
+    let accounts = decoder.usize()?;
+    let mut map = HashMap::new();
+    for _ in 0..accounts {
+        let account = decoder.string()?;
+        map.insert(account, decoder.u64()?);
+    }
+    Ok(map)
 }
 
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub id: String,
+    pub from_account: String,
+    pub to_account: String,
     pub amount: u64,
+    pub fee: u64,   // paid by the sender to the block's proposer and validators
+    pub nonce: u64, // position in the sending account's sequence
     pub from_shard: usize,
     pub to_shard: usize,
+    pub public_key: PublicKey, // sender's key; `from_account` must be its address
+    pub signature: Signature,  // over `signing_bytes`
     pub status: TransactionStatus,
 }
 
+impl Transaction {
+    // Size of the canonical encoding, used for admission limits.
+    pub fn size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    // What the sender signs: the canonical encoding of every field that is
+    // sent, apart from the signature itself.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::versioned();
+        self.encode_payload(&mut encoder);
+        encoder.finish()
+    }
+
+    // Sign as the owner of `keypair`, which should be `from_account`'s key.
+    pub fn sign(&mut self, keypair: &Keypair) {
+        self.public_key = keypair.public_key();
+        self.signature = keypair.sign(&self.signing_bytes());
+    }
+
+    // The signature is valid and made by the key that owns `from_account`.
+    pub fn verify_signature(&self) -> Result<(), SignatureError> {
+        let address = keys::address_of(&self.public_key);
+        if address != self.from_account {
+            return Err(SignatureError::AddressMismatch {
+                address: self.from_account.clone(),
+                expected: address,
+            });
+        }
+        self.public_key.verify(&self.signing_bytes(), &self.signature)
+    }
+
+    fn encode_payload(&self, encoder: &mut Encoder) {
+        encoder.put_str(&self.id);
+        encoder.put_str(&self.from_account);
+        encoder.put_str(&self.to_account);
+        encoder.put_u64(self.amount);
+        encoder.put_u64(self.fee);
+        encoder.put_u64(self.nonce);
+        encoder.put_usize(self.from_shard);
+        encoder.put_usize(self.to_shard);
+        self.public_key.encode(encoder);
+    }
+}
+
+// `status` is local bookkeeping and is not encoded; a decoded transaction
+// always starts out pending.
+impl Encode for Transaction {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.encode_payload(encoder);
+        self.signature.encode(encoder);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            id: decoder.string()?,
+            from_account: decoder.string()?,
+            to_account: decoder.string()?,
+            amount: decoder.u64()?,
+            fee: decoder.u64()?,
+            nonce: decoder.u64()?,
+            from_shard: decoder.usize()?,
+            to_shard: decoder.usize()?,
+            public_key: PublicKey::decode(decoder)?,
+            signature: Signature::decode(decoder)?,
+            status: TransactionStatus::Pending,
+        })
+    }
+}
+
+// A transfer on its way to the shard that credits it, carrying proof that the
+// sending shard debited it: the transaction's inclusion proof in the sending
+// shard's block. This is the only way a credit for another shard's account
+// gets into a receiving shard.
+#[derive(Debug, Clone)]
+pub struct CrossShardTransfer {
+    pub transaction: Transaction,
+    pub debit_proof: InclusionProof,
+}
+
+impl CrossShardTransfer {
+    // Whether the proof shows this exact transaction in a block of the shard
+    // it claims to come from, and that block is one `trusted` holds for that
+    // shard. A header made up to fit the proof is not.
+    pub fn is_proven(&self, trusted: &RemoteChains) -> bool {
+        let tx = &self.transaction;
+        let block_hash = self.debit_proof.header.hash();
+        self.debit_proof.header.shard_id == tx.from_shard
+            && trusted.contains(tx.from_shard, &block_hash)
+            && self.debit_proof.covers(tx)
+            && self.debit_proof.verify(&block_hash)
+    }
+}
+
+impl Encode for CrossShardTransfer {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.transaction.encode(encoder);
+        self.debit_proof.encode(encoder);
+    }
+}
+
+impl Decode for CrossShardTransfer {
+    fn decode(decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(CrossShardTransfer {
+            transaction: Transaction::decode(decoder)?,
+            debit_proof: InclusionProof::decode(decoder)?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TransactionStatus {
     Pending,
     Processing,
     Completed,
-    Failed,
+    Failed(FailureReason),
+    Expired, // waited in the pool past its TTL
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureReason {
+    Rejected(RejectionReason), // refused by a validation rule
+    Execution(ExecutionError), // valid, but could not be applied to the ledger
+    Evicted,                   // pushed out of a full pool by better-paying transactions
+}
+
+// Reorder a batch so each sender's transactions run in nonce order, keeping
+// the positions the sender was given in the batch.
+fn in_nonce_order(batch: Vec<Transaction>) -> Vec<Transaction> {
+    let senders: Vec<String> = batch.iter().map(|tx| tx.from_account.clone()).collect();
+    let mut by_sender: HashMap<String, Vec<Transaction>> = HashMap::new();
+    for tx in batch {
+        by_sender.entry(tx.from_account.clone()).or_default().push(tx);
+    }
+    for txs in by_sender.values_mut() {
+        // highest first, so popping yields the lowest
+        txs.sort_by_key(|tx| std::cmp::Reverse(tx.nonce));
+    }
+    senders
+        .iter()
+        .map(|sender| by_sender.get_mut(sender).and_then(|txs| txs.pop()).unwrap())
+        .collect()
 }
 
 #[derive(Debug)]
 pub struct Shard {
     pub id: usize,
-    generator: PohGenerator,
+    poh: PohService, // the shard's PoH clock, running on its own thread
+    poh_entries: Receiver<PohEntry>, // what `poh` has emitted since the last reset
+    tick_height: u64,
     pub epoch: usize,
     transaction_count: usize,
-    transaction_pool: Vec<Transaction>,
-    ledger: HashMap<String, u64>,
-    pub blocks: Vec<Block>,
+    transaction_pool: Mempool,
+    ledger: Ledger,
+    pub blocks: Vec<Block>, // the chosen chain, from `base` to the head of `tree`
+    base: ChainTip, // where `blocks` continues from: genesis, or the last settled checkpoint
+    base_ledger: Ledger, // ledger state at `base`, replayed from on a reorg
+    tree: BlockTree,     // every known block after `base`, competing branches included
     validators: Vec<Validator>,
     min_transactions_per_block: usize,
     max_transactions_per_block: usize,
-    last_block_time: Instant,
-    block_time_threshold: Duration,
+    poh_config: PohConfig,
+    pending_entries: Vec<PohEntry>, // ticks and arrival stamps since the tip, carried into the next block
+    last_block_slot: u64,
     epoch_threshold: usize,
     processed_transactions: HashSet<String>,
-    pending_cross_shard_txs: Vec<Transaction>,
+    pending_cross_shard_txs: Vec<CrossShardTransfer>, // debited here, waiting for gossip to deliver
+    pending_headers: Vec<BlockHeader>, // blocks added here, waiting for gossip to announce them
+    remote_chains: RemoteChains,       // blocks of other shards that debit proofs are checked against
+    transaction_validator: ValidationPipeline,
+    failed_transactions: FailedTransactions, // the most recent failures, by id
+    status_index: StatusIndex, // status changes, outliving the pool
+    tx_index: TransactionIndex, // where each transaction on the chain is
     pub epoch_start_time: Instant,
-    pub pending_checkpoint: Option<Checkpoint>,
+    pub last_checkpoint: Option<Checkpoint>, // the checkpoint `base` was settled at, if any
+    clock: SharedClock,
+    block_store: Option<BlockStore>,
+    snapshot_store: Option<SnapshotStore>,
+}
+
+#[derive(Debug)]
+pub enum AppendError {
+    Invalid(BlockError),
+    UnknownParent(Hash),
+    Execution { tx_id: String, error: ExecutionError },
+    Fees(ExecutionError),
+    SupplyInvariant(SupplyViolation),
+    StateRootMismatch { expected: Hash, found: Hash },
+    DuplicateTransaction(DuplicateTransaction),
+    Storage(io::Error),
+}
+
+impl fmt::Display for AppendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppendError::Invalid(error) => write!(f, "invalid block: {:?}", error),
+            AppendError::UnknownParent(parent) => write!(f, "unknown parent block {}", parent),
+            AppendError::Execution { tx_id, error } => write!(f, "transaction {} failed to execute: {:?}", tx_id, error),
+            AppendError::Fees(error) => write!(f, "fees could not be paid: {:?}", error),
+            AppendError::SupplyInvariant(violation) => write!(f, "supply invariant broken: {:?}", violation),
+            AppendError::StateRootMismatch { expected, found } => {
+                write!(f, "state root {} does not match the executed state {}", found, expected)
+            }
+            AppendError::DuplicateTransaction(duplicate) => {
+                write!(f, "transaction {} is already on the chain at {:?}", duplicate.tx_id, duplicate.location)
+            }
+            AppendError::Storage(error) => write!(f, "storage error: {}", error),
+        }
+    }
+}
+
+impl From<BlockError> for AppendError {
+    fn from(error: BlockError) -> Self {
+        AppendError::Invalid(error)
+    }
+}
+
+impl From<DuplicateTransaction> for AppendError {
+    fn from(duplicate: DuplicateTransaction) -> Self {
+        AppendError::DuplicateTransaction(duplicate)
+    }
 }
 
 impl Shard {
+    #[allow(dead_code)] // the node builds its shards from a calibrated config
     pub fn new(id: usize, batch_size: usize, max_transactions_per_block: usize, validators: Vec<Validator>) -> Self {
+        Self::with_poh_config(
+            id,
+            batch_size,
+            max_transactions_per_block,
+            validators,
+            PohConfig::default(),
+            clock::system_clock(),
+        )
+    }
+
+    // Same as `new`, with tick and slot sizes taken from a calibrated
+    // `poh_config` and entry and block timestamps read from `clock`.
+    pub fn with_poh_config(
+        id: usize,
+        batch_size: usize,
+        max_transactions_per_block: usize,
+        validators: Vec<Validator>,
+        poh_config: PohConfig,
+        clock: SharedClock,
+    ) -> Self {
+        let generator = PohGenerator::with_config(batch_size, poh_config, clock.clone());
+        let poh = PohService::start(generator, poh_config.tick_duration(), clock.clone());
+        let poh_entries = poh.subscribe();
+
         Shard {
             id,
-            generator: PohGenerator::new(batch_size),
+            poh,
+            poh_entries,
+            tick_height: 0,
             epoch: 0,
             transaction_count: 0,
-            transaction_pool: Vec::new(),
-            ledger: HashMap::new(),
+            transaction_pool: Mempool::new(MempoolConfig::default()),
+            ledger: Ledger::new(),
             blocks: Vec::new(),
+            base: ChainTip::default(),
+            base_ledger: Ledger::new(),
+            tree: BlockTree::new(ChainTip::default(), ForkChoice::default()),
             validators,
             min_transactions_per_block: 100,
             max_transactions_per_block,
-            last_block_time: Instant::now(),
-            block_time_threshold: Duration::from_secs(15),
+            poh_config,
+            pending_entries: Vec::new(),
+            last_block_slot: 0,
             epoch_threshold: 10,
             processed_transactions: HashSet::new(),
             pending_cross_shard_txs: Vec::new(),
+            pending_headers: Vec::new(),
+            remote_chains: RemoteChains::new(),
+            transaction_validator: validation::default_pipeline(),
+            failed_transactions: FailedTransactions::default(),
+            status_index: StatusIndex::default(),
+            tx_index: TransactionIndex::new(),
             epoch_start_time: Instant::now(),
-            pending_checkpoint: None,
+            last_checkpoint: None,
+            clock,
+            block_store: None,
+            snapshot_store: None,
         }
     }
 
+    // Persist blocks and snapshots under `dir` from now on, first restoring
+    // whatever is already stored there: the newest valid snapshot, then the
+    // stored blocks after it. Those blocks are audited as one chain and then
+    // re-executed. At the first one that fails the log is copied aside and
+    // cut back there; only a torn tail record is ever dropped outright.
+    pub fn open_storage(&mut self, dir: &Path, snapshot_retention: usize) -> io::Result<()> {
+        let snapshots = SnapshotStore::open(&dir.join("snapshots"), self.id, snapshot_retention)?;
+        let (mut store, stored_blocks) = BlockStore::open(&dir.join("blocks"), self.id)?;
+
+        self.blocks.clear();
+        self.base = ChainTip::default();
+        if let Some(checkpoint) = snapshots.load_latest()? {
+            println!("Shard {}: Restoring snapshot at block #{}", self.id, checkpoint.block_height);
+            self.base = checkpoint.tip();
+            self.ledger = checkpoint.ledger();
+            self.processed_transactions = checkpoint.processed_transactions_snapshot.clone();
+            self.restore_pool(checkpoint.transaction_pool_snapshot.clone());
+            self.last_checkpoint = Some(checkpoint);
+        }
+        self.base_ledger = self.ledger.clone();
+        self.tree.reset(self.base);
+        self.tx_index.clear();
+
+        let (settled, mut pending): (Vec<Block>, Vec<Block>) = stored_blocks
+            .into_iter()
+            .partition(|block| block.header.block_number <= self.base.block_number);
+        for block in &settled {
+            // settled by the snapshot, but still looked up by id
+            if let Err(duplicate) = self.tx_index.insert_block(block) {
+                println!("Shard {}: Stored block #{} repeats {:?}", self.id, block.header.block_number, duplicate);
+            }
+        }
+
+        // audit the stored chain as a whole before executing any of it
+        let mut failure = None;
+        match verifier::verify_chain(&self.base, &pending, &self.block_rules()) {
+            Ok(verification) => println!(
+                "Shard {}: Verified {} stored PoH entries ({} hashes) in {} ms, {:.0} hashes/s",
+                self.id,
+                verification.entries_verified,
+                verification.hashes_verified,
+                verification.elapsed.as_millis(),
+                verification.hashes_per_second
+            ),
+            Err((block_number, error)) => {
+                pending.retain(|block| block.header.block_number < block_number);
+                failure = Some((block_number, AppendError::from(error)));
+            }
+        }
+        for block in pending {
+            let executed = self
+                .execute_block(&self.ledger, &block)
+                .and_then(|ledger| self.tx_index.insert_block(&block).map(|()| ledger).map_err(AppendError::from));
+            let ledger = match executed {
+                Ok(ledger) => ledger,
+                Err(error) => {
+                    failure = Some((block.header.block_number, error));
+                    break;
+                }
+            };
+            self.ledger = ledger;
+            for entry in &block.poh_entries {
+                self.processed_transactions.extend(entry.transactions.iter().cloned());
+            }
+            self.tree.insert(block.clone(), self.block_weight(&block));
+            self.pending_headers.push(block.header.clone());
+            self.blocks.push(block);
+        }
+        if let Some((block_number, error)) = failure {
+            let aside = store.set_aside_from(block_number)?;
+            println!(
+                "Shard {}: Stored block #{} failed verification ({}). Continuing without it and later blocks; the log was kept as {}.",
+                self.id,
+                block_number,
+                error,
+                aside.display()
+            );
+        }
+        // the snapshot's pool predates the blocks replayed after it
+        let settled: Vec<String> = self
+            .transaction_pool
+            .iter()
+            .filter(|tx| self.tx_index.get(&tx.id).is_some())
+            .map(|tx| tx.id.clone())
+            .collect();
+        for tx_id in settled {
+            self.transaction_pool.remove(&tx_id);
+        }
+        self.reset_poh(self.poh_head());
+
+        println!(
+            "Shard {}: Reloaded {} blocks after block #{} from {}",
+            self.id,
+            self.blocks.len(),
+            self.base.block_number,
+            dir.display()
+        );
+        self.block_store = Some(store);
+        self.snapshot_store = Some(snapshots);
+        Ok(())
+    }
+
+    // Write `checkpoint` as a snapshot file, if the shard has storage.
+    pub fn save_snapshot(&self, checkpoint: &Checkpoint) -> io::Result<()> {
+        if let Some(snapshots) = &self.snapshot_store {
+            let path = snapshots.save(checkpoint)?;
+            println!("Shard {}: Saved snapshot {}", self.id, path.display());
+        }
+        Ok(())
+    }
+
     pub fn get_validators(&self) -> &Vec<Validator> {
         &self.validators
     }
 
-    pub fn get_transaction_pool(&self) -> &Vec<Transaction> {
+    pub fn get_transaction_pool(&self) -> &Mempool {
         &self.transaction_pool
     }
 
+    // Replace the pool's limits. Transactions already pooled are kept as far
+    // as the new limits allow.
+    // How competing branches are ranked, from the next block the shard adds.
+    pub fn set_fork_choice(&mut self, fork_choice: ForkChoice) {
+        self.tree.set_fork_choice(fork_choice);
+    }
+
+    pub fn set_mempool_config(&mut self, config: MempoolConfig) {
+        let pooled: Vec<Transaction> = self.transaction_pool.iter().cloned().collect();
+        self.transaction_pool = Mempool::new(config);
+        self.restore_pool(pooled);
+    }
+
+    fn restore_pool(&mut self, transactions: Vec<Transaction>) {
+        self.transaction_pool.clear();
+        for tx in transactions {
+            self.requeue(tx);
+        }
+    }
+
+    pub fn get_failed_transactions(&self) -> &FailedTransactions {
+        &self.failed_transactions
+    }
+
+    pub fn get_status_index(&self) -> &StatusIndex {
+        &self.status_index
+    }
+
+    fn set_status(&mut self, tx: &mut Transaction, status: TransactionStatus, reason: StatusReason, block: Option<BlockRef>) {
+        tx.status = status.clone();
+        let change = StatusChange {
+            status,
+            reason,
+            at: self.clock.now(),
+            block,
+        };
+        self.status_index.record(&tx.id, change);
+    }
+
+    // Mark `tx` failed for good and keep it with the other failures.
+    fn fail(&mut self, mut tx: Transaction, reason: FailureReason, block: Option<BlockRef>) {
+        self.set_status(
+            &mut tx,
+            TransactionStatus::Failed(reason.clone()),
+            StatusReason::Failed(reason),
+            block,
+        );
+        self.failed_transactions.insert(tx);
+    }
+
+    // Genesis allocation: credit `account` with newly created funds. Call this
+    // before `open_storage`, so stored blocks replay on top of it.
+    pub fn fund_account(&mut self, account: &str, amount: u64) -> Result<(), ExecutionError> {
+        self.ledger.mint(account, amount)?;
+        self.base_ledger.mint(account, amount)
+    }
+
+    // Add a rule that every transaction must pass before it goes into a block.
+    pub fn register_transaction_rule(&mut self, rule: Box<dyn TransactionValidator>) {
+        self.transaction_validator.register(rule);
+    }
+
+    // Transactions in the blocks held in memory, oldest first.
     pub fn get_processed_transactions(&self) -> Vec<&Transaction> {
-        self.transaction_pool.iter()
-            .filter(|tx| tx.status == TransactionStatus::Completed)
-            .collect()
+        self.blocks.iter().flat_map(|block| &block.transactions).collect()
     }
 
+    // Transactions on the whole chain, settled blocks included.
     pub fn get_processed_transaction_count(&self) -> usize {
-        self.blocks.iter().map(|block| block.poh_entries[0].transactions.len()).sum()
+        self.tx_index.len()
     }
 
     pub fn get_pending_cross_shard_txs_len(&self) -> usize {
         self.pending_cross_shard_txs.len()
     }
 
-    pub fn drain_pending_cross_shard_txs(&mut self) -> Vec<Transaction> {
+    pub fn drain_pending_cross_shard_txs(&mut self) -> Vec<CrossShardTransfer> {
         self.pending_cross_shard_txs.drain(..).collect()
     }
 
-    pub fn get_transaction_by_id(&self, tx_id: &str) -> Option<&Transaction> {
-        self.transaction_pool.iter().find(|tx| tx.id == tx_id)
+    // Put a transfer the receiving shard had no room for back in the outbox,
+    // to be offered again with the next gossip.
+    pub fn hold_back_transfer(&mut self, transfer: CrossShardTransfer) {
+        self.pending_cross_shard_txs.push(transfer);
     }
 
-    pub fn process_transactions(&mut self, transactions: Vec<Transaction>) {
-        for mut tx in transactions {
-            if tx.to_shard == self.id {
-                println!("Shard {}: Adding transaction {} to pool", self.id, tx.id);
-                tx.status = TransactionStatus::Pending;
-                self.transaction_pool.push(tx.clone());
+    pub fn drain_pending_headers(&mut self) -> Vec<BlockHeader> {
+        self.pending_headers.drain(..).collect()
+    }
+
+    // Take the header of a block another shard has added, announced by that
+    // shard. It is trusted from now on if it extends a block of that shard
+    // already trusted here.
+    pub fn receive_header(&mut self, header: &BlockHeader) -> bool {
+        header.shard_id != self.id && self.remote_chains.add_header(header)
+    }
+
+    // Look `tx_id` up in the pool, on the chain and among the failures, with
+    // its status as this shard last recorded it.
+    pub fn get_transaction_by_id(&self, tx_id: &str) -> Option<Transaction> {
+        let mut tx = self
+            .transaction_pool
+            .get(tx_id)
+            .cloned()
+            .or_else(|| {
+                let block = self.block_at(self.tx_index.get(tx_id)?)?;
+                block.transactions.iter().find(|tx| tx.id == tx_id).cloned()
+            })
+            .or_else(|| self.failed_transactions.get(tx_id).cloned())?;
+        tx.status = self.current_status(tx_id).unwrap_or(tx.status);
+        Some(tx)
+    }
+
+    // A transaction on the chain is completed even if this shard has no
+    // status history for it, as after a restart.
+    pub fn current_status(&self, tx_id: &str) -> Option<TransactionStatus> {
+        match self.status_index.current(tx_id) {
+            Some(change) => Some(change.status.clone()),
+            None => self.tx_index.get(tx_id).map(|_| TransactionStatus::Completed),
+        }
+    }
+
+    // The block at `location`, borrowed from memory or else read back from
+    // the block store.
+    fn block_at(&self, location: &TransactionLocation) -> Option<Cow<'_, Block>> {
+        let in_memory = location
+            .block_number
+            .checked_sub(self.base.block_number + 1)
+            .and_then(|index| self.blocks.get(index as usize))
+            .filter(|block| block.block_hash == location.block_hash);
+        if let Some(block) = in_memory {
+            return Some(Cow::Borrowed(block));
+        }
+        let store = self.block_store.as_ref()?;
+        match store.get_by_hash(&location.block_hash) {
+            Ok(block) => block.map(Cow::Owned),
+            Err(e) => {
+                eprintln!("Shard {}: Failed to read block {}: {}", self.id, location.block_hash, e);
+                None
             }
         }
+    }
+
+    // Status, chain location and inclusion proof for `tx_id`, if this shard
+    // knows of it. The proof is missing only if the block has been pruned
+    // from memory and there is no block store to read it back from.
+    pub fn transaction_receipt(&self, tx_id: &str) -> Option<TransactionReceipt> {
+        let status = self.current_status(tx_id)?;
+        let location = self.tx_index.get(tx_id).copied();
+        let block = location.and_then(|location| self.block_at(&location));
+        let proof = block.as_ref().and_then(|block| block.prove_transaction(tx_id));
+        let entry_proof = location.zip(block.as_ref()).and_then(|(location, block)| {
+            block.poh_entries.get(location.entry_index)?.prove_transaction(tx_id)
+        });
+        Some(TransactionReceipt {
+            tx_id: tx_id.to_string(),
+            shard_id: self.id,
+            status,
+            location,
+            proof,
+            entry_proof,
+        })
+    }
+
+    // Offer transactions sent from this shard's accounts to the pool,
+    // reporting what happened to each in order. Those refused with
+    // `AdmissionError::PoolFull` can be offered again later. Credits from
+    // other shards only come in through `process_cross_shard_transaction`.
+    pub fn process_transactions(&mut self, transactions: Vec<Transaction>) -> Vec<Result<(), AdmissionError>> {
+        let results = transactions
+            .into_iter()
+            .map(|tx| {
+                if tx.from_shard == self.id {
+                    self.admit(tx)
+                } else {
+                    Err(AdmissionError::Rejected(RejectionReason::WrongShard {
+                        shard_id: self.id,
+                        from_shard: tx.from_shard,
+                        to_shard: tx.to_shard,
+                    }))
+                }
+            })
+            .collect();
 
         self.check_and_create_block();
+        results
     }
 
-    pub fn process_cross_shard_transaction(&mut self, mut transaction: Transaction) {
-        if !self.processed_transactions.contains(&transaction.id) {
-            println!(
-                "Shard {}: Processing cross-shard transaction {} from Shard {}",
-                self.id, transaction.id, transaction.from_shard
-            );
+    // Put `tx` in the pool if it is signed by the sending account, its nonce
+    // has not already been used and there is room. A nonce ahead of the
+    // account's sequence is fine here; the transaction waits in the pool
+    // until the ones before it have executed.
+    fn admit(&mut self, mut tx: Transaction) -> Result<(), AdmissionError> {
+        // the index also covers blocks settled before the last checkpoint
+        if self.processed_transactions.contains(&tx.id) || self.tx_index.get(&tx.id).is_some() {
+            return Err(AdmissionError::Duplicate);
+        }
+        let rejection = match (tx.verify_signature(), self.ledger.check_nonce(&tx, self.id)) {
+            (Err(error), _) => Some(RejectionReason::InvalidSignature(error)),
+            (_, Err(ExecutionError::StaleNonce { expected, found, .. })) => {
+                Some(RejectionReason::StaleNonce { expected, found })
+            }
+            _ => None,
+        };
+        if let Some(reason) = rejection {
+            println!("Shard {}: Rejected transaction {}: {:?}", self.id, tx.id, reason);
+            self.fail(tx, FailureReason::Rejected(reason.clone()), None);
+            return Err(AdmissionError::Rejected(reason));
+        }
 
-            transaction.status = TransactionStatus::Pending;
-            self.transaction_pool.push(transaction.clone());
+        tx.status = TransactionStatus::Pending;
+        let tx_id = tx.id.clone();
+        match self.pool_insert(tx.clone()) {
+            Ok(evicted) => {
+                println!("Shard {}: Adding transaction {} to pool", self.id, tx_id);
+                // stamp the arrival into PoH, so the chain shows when it was first seen
+                if let Err(e) = self.poh.record(tx.canonical_hash()) {
+                    println!("Shard {}: Could not stamp transaction {}: {}", self.id, tx_id, e);
+                }
+                self.set_status(&mut tx, TransactionStatus::Pending, StatusReason::Admitted, None);
+                self.mark_evicted(evicted);
+                Ok(())
+            }
+            Err(AdmissionError::Rejected(reason)) => {
+                println!("Shard {}: Rejected transaction {}: {:?}", self.id, tx_id, reason);
+                self.fail(tx, FailureReason::Rejected(reason.clone()), None);
+                Err(AdmissionError::Rejected(reason))
+            }
+            Err(e) => {
+                println!("Shard {}: Not adding transaction {} to pool: {:?}", self.id, tx_id, e);
+                Err(e)
+            }
+        }
+    }
 
-            self.check_and_create_block();
+    // Put a transaction that was already admitted back in the pool, after its
+    // block was orphaned or when restoring a snapshot. If there is no longer
+    // room for it, it is dropped as evicted.
+    fn requeue(&mut self, mut tx: Transaction) {
+        tx.status = TransactionStatus::Pending;
+        let requeued = if tx.from_shard != self.id {
+            self.transaction_pool.readmit_credit(tx.clone()).map(|()| Vec::new())
         } else {
-            println!(
-                "Shard {}: Ignoring duplicate cross-shard transaction {}",
-                self.id, transaction.id
-            );
+            self.transaction_pool.insert(tx.clone(), self.clock.now())
+        };
+        match requeued {
+            Ok(evicted) => self.mark_evicted(evicted),
+            Err(AdmissionError::Duplicate) => {}
+            Err(_) => self.mark_evicted(vec![tx]),
+        }
+    }
+
+    // Credits for transfers debited on another shard go in the pool's credit
+    // queue, which has a cap of its own and never evicts; the rest compete
+    // for space by fee.
+    fn pool_insert(&mut self, tx: Transaction) -> Result<Vec<Transaction>, AdmissionError> {
+        if tx.from_shard != self.id {
+            return self.transaction_pool.insert_credit(tx).map(|()| Vec::new());
         }
+        self.transaction_pool.insert(tx, self.clock.now())
     }
 
-    pub fn add_pending_cross_shard_tx(&mut self, transaction: Transaction) {
+    fn mark_evicted(&mut self, evicted: Vec<Transaction>) {
+        for tx in evicted {
+            println!("Shard {}: Evicted transaction {} from the pool", self.id, tx.id);
+            self.fail(tx, FailureReason::Evicted, None);
+        }
+    }
+
+    // Drop transactions that have waited in the pool past their TTL.
+    fn expire_transactions(&mut self) {
+        for mut tx in self.transaction_pool.expire(self.clock.now()) {
+            println!("Shard {}: Transaction {} expired in the pool", self.id, tx.id);
+            self.set_status(&mut tx, TransactionStatus::Expired, StatusReason::TtlElapsed, None);
+            self.failed_transactions.insert(tx);
+        }
+    }
+
+    // The nonce `account`'s next transaction should carry: the ledger's next
+    // nonce, moved past any that are already waiting in the pool.
+    pub fn next_nonce(&self, account: &str) -> u64 {
+        let waiting: HashSet<u64> = self
+            .transaction_pool
+            .iter()
+            .filter(|tx| tx.from_account == account)
+            .map(|tx| tx.nonce)
+            .collect();
+        let mut nonce = self.ledger.next_nonce(account);
+        while waiting.contains(&nonce) && nonce < u64::MAX {
+            nonce += 1;
+        }
+        nonce
+    }
+
+    // Admit the credit side of a transfer another shard has debited. The
+    // transfer must be addressed here and come with a valid debit proof.
+    pub fn process_cross_shard_transaction(&mut self, transfer: CrossShardTransfer) -> Result<(), AdmissionError> {
+        let transaction = transfer.transaction.clone();
         if !self.processed_transactions.contains(&transaction.id) {
             println!(
-                "Shard {}: Adding pending cross-shard transaction {}",
-                self.id, transaction.id
+                "Shard {}: Processing cross-shard transaction {} from Shard {}",
+                self.id, transaction.id, transaction.from_shard
             );
-            self.pending_cross_shard_txs.push(transaction);
 
-            println!(
-                "Shard {}: Pending cross-shard transactions count: {}",
-                self.id,
-                self.pending_cross_shard_txs.len()
-            );
+            if transaction.to_shard != self.id || transaction.from_shard == self.id {
+                return Err(AdmissionError::Rejected(RejectionReason::WrongShard {
+                    shard_id: self.id,
+                    from_shard: transaction.from_shard,
+                    to_shard: transaction.to_shard,
+                }));
+            }
+            if !transfer.is_proven(&self.remote_chains) {
+                println!("Shard {}: Rejected transaction {}: {:?}", self.id, transaction.id, RejectionReason::UnprovenDebit);
+                self.fail(transaction, FailureReason::Rejected(RejectionReason::UnprovenDebit), None);
+                return Err(AdmissionError::Rejected(RejectionReason::UnprovenDebit));
+            }
+
+            let result = self.admit(transaction);
+
+            self.check_and_create_block();
+            result
         } else {
             println!(
-                "Shard {}: Ignoring pending cross-shard transaction {} (already processed).",
+                "Shard {}: Ignoring duplicate cross-shard transaction {}",
                 self.id, transaction.id
             );
+            Err(AdmissionError::Duplicate)
         }
     }
 
     pub fn check_and_create_block(&mut self) {
-        let total_transactions = self.transaction_pool.len() + self.pending_cross_shard_txs.len();
-        let time_since_last_block = self.last_block_time.elapsed();
+        self.expire_transactions();
+        self.sync_poh();
+        let total_transactions = self.transaction_pool.len();
+        let current_slot = self.current_slot();
 
-        if time_since_last_block >= self.block_time_threshold {
-            println!("Shard {}: Block time threshold exceeded. Creating block.", self.id);
+        // every slot gets a block, even one of ticks alone, so the chain keeps
+        // pace with PoH whether or not transactions are coming in
+        if current_slot > self.last_block_slot {
+            println!("Shard {}: Slot {} started. Creating block.", self.id, current_slot);
             self.create_block();
-            self.last_block_time = Instant::now();
+            self.last_block_slot = current_slot;
             return;
         }
 
@@ -193,10 +924,10 @@ impl Shard {
         if total_transactions >= dynamic_min_transactions {
             println!("Shard {}: Min transaction threshold reached. Creating block.", self.id);
             self.create_block();
-            self.last_block_time = Instant::now();
+            self.last_block_slot = current_slot;
         } else {
             println!(
-                "Shard {}: Waiting for more transactions or block time threshold. Current pool size: {}.",
+                "Shard {}: Waiting for more transactions or the next slot. Current pool size: {}.",
                 self.id, total_transactions
             );
         }
@@ -206,6 +937,26 @@ impl Shard {
         }
     }
 
+    // Collect what PoH has emitted up to now, every tick due included.
+    fn sync_poh(&mut self) {
+        match self.poh.sync() {
+            Ok(tick_height) => self.tick_height = tick_height,
+            Err(e) => println!("Shard {}: {}", self.id, e),
+        }
+        self.pending_entries.extend(self.poh_entries.try_iter());
+    }
+
+    // Continue PoH from `hash`, dropping entries made since then.
+    fn reset_poh(&mut self, hash: Hash) {
+        self.poh_entries = self.poh.reset(hash);
+        self.pending_entries.clear();
+    }
+
+    // PoH slot the shard is in, by the ticks its PoH clock has produced.
+    pub fn current_slot(&self) -> u64 {
+        self.tick_height / self.poh_config.ticks_per_slot.max(1)
+    }
+
     fn calculate_dynamic_min_transactions(&self, total_transactions: usize) -> usize {
         if total_transactions > 1500 {
             1500
@@ -219,25 +970,26 @@ impl Shard {
     }
 
     fn create_block(&mut self) {
-        let total_transactions = self.transaction_pool.len() + self.pending_cross_shard_txs.len();
+        let block_creation_time = Instant::now();
     
-        if total_transactions < self.min_transactions_per_block {
-            println!(
-                "Shard {}: Not enough transactions to fill the block. Current pool size: {}. Waiting for more transactions...",
-                self.id, total_transactions
-            );
+        let building = BlockRef::building(self.tip().block_number + 1);
+        let mut transactions_to_include = self.select_transactions();
+        for tx in transactions_to_include.iter_mut() {
+            self.set_status(tx, TransactionStatus::Processing, StatusReason::Selected, Some(building));
+        }
+
+        let transactions_to_include =
+            in_nonce_order(self.reject_invalid_transactions(transactions_to_include, building));
+        let proposer = self.proposer_for(building.block_number);
+        let mut staged_ledger = self.ledger.clone();
+        let (mut transactions_to_include, fees) =
+            self.execute_transactions(&mut staged_ledger, transactions_to_include, building);
+        if let Err(e) = self.pay_fees(&mut staged_ledger, proposer, fees) {
+            println!("Shard {}: Could not pay block fees ({:?}). Waiting for the next block.", self.id, e);
+            self.return_to_pool(&mut transactions_to_include, StatusReason::BlockRejected(format!("{:?}", e)), building);
             return;
         }
     
-        let block_creation_time = Instant::now();
-    
-        let mut transactions_to_include = Vec::new();
-    
-        transactions_to_include.extend(self.pending_cross_shard_txs.drain(..));
-        transactions_to_include.extend(self.transaction_pool.drain(..));
-    
-        transactions_to_include.truncate(self.max_transactions_per_block);
-    
         let tx_strings: Vec<String> = transactions_to_include.iter().map(|tx| tx.id.clone()).collect();
     
         let mut validator_performance: HashMap<usize, ValidatorPerformance> = HashMap::new();
@@ -246,26 +998,50 @@ impl Shard {
             validator_performance.insert(validator.id, ValidatorPerformance::from_validator(validator));
         }
     
-        match self.generator.generate_entries(tx_strings, validator_performance) {
-            Ok(entries) => {
-                let block_number = self.blocks.len() as u64 + 1;
-                let previous_hash = self
-                    .blocks
-                    .last()
-                    .map(|block| block.block_hash.clone())
-                    .unwrap_or_else(|| "0".to_string());
-    
-                let block = Block::new(block_number, entries.clone(), &previous_hash);
+        let poh_head = self.poh_head();
+
+        let recorded = if tx_strings.is_empty() {
+            Ok(Vec::new())
+        } else {
+            self.poh.record_transactions(tx_strings, validator_performance)
+        };
+
+        match recorded {
+            Ok(_) => {
+                // everything since the tip, the batch last, so the entries run on from it
+                self.sync_poh();
+                let entries = std::mem::take(&mut self.pending_entries);
+                let tip = self.tip();
+                let block_number = tip.block_number + 1;
+                let previous_hash = tip.block_hash;
     
-                if self.validate_block_with_validators(&block) {
-                    self.blocks.push(block.clone());
+                let header = BlockHeader::new(
+                    block_number,
+                    previous_hash,
+                    self.id,
+                    self.epoch,
+                    proposer,
+                    staged_ledger.state_root(),
+                    self.clock.now().timestamp(),
+                );
+                let block = Block::new(header, poh_head, entries.clone(), transactions_to_include.clone());
     
-                    for tx in transactions_to_include.iter_mut() {
-                        tx.status = TransactionStatus::Completed;
-                        self.processed_transactions.insert(tx.id.clone());
-                        self.transaction_count += 1;
-                        println!("Transaction {} status updated to Completed.", tx.id);
-                    }
+                let verdict = if self.validate_block_with_validators(&block) {
+                    self.append_block(block.clone()).map_err(|e| e.to_string())
+                } else {
+                    Err("rejected by validators".to_string())
+                };
+
+                let built = BlockRef::built(block_number, block.block_hash);
+                if let Err(reason) = verdict {
+                    println!(
+                        "Shard {}: Block #{} failed validation ({}). Discarding block.",
+                        self.id, block_number, reason
+                    );
+                    self.reset_poh(poh_head);
+                    self.return_to_pool(&mut transactions_to_include, StatusReason::BlockRejected(reason), built);
+                } else {
+                    self.include_transactions(&block);
     
                     let block_duration = block_creation_time.elapsed();
     
@@ -283,23 +1059,426 @@ impl Shard {
     
                     let current_time = chrono::Utc::now();
                     println!("#{} created in {} ms at {}. {} transactions included",
-                        block.block_number, block_duration.as_millis(),
-                        current_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        block.header.block_number, block_duration.as_millis(),
+                        current_time.format("%Y-%m-%d %H:%M:%S"),
                         transactions_to_include.len()
                     );
-                } else {
-                    println!("Shard {}: Block #{} failed validation. Discarding block.", self.id, block_number);
-                    self.transaction_pool.extend(transactions_to_include);
                 }
             }
             Err(e) => {
                 println!("Shard {}: Error processing transactions: {}", self.id, e);
-                self.transaction_pool.extend(transactions_to_include);
+                self.return_to_pool(&mut transactions_to_include, StatusReason::BlockRejected(e.to_string()), building);
+            }
+        }
+    }
+
+    // The block being built for `transactions` was abandoned. They are still
+    // in the pool and wait there, pending, for the next one.
+    fn return_to_pool(&mut self, transactions: &mut [Transaction], reason: StatusReason, block: BlockRef) {
+        for tx in transactions.iter_mut() {
+            self.set_status(tx, TransactionStatus::Pending, reason.clone(), Some(block));
+        }
+    }
+
+    // Validators take turns proposing in their current ranking order.
+    fn proposer_for(&self, block_number: u64) -> usize {
+        if self.validators.is_empty() {
+            return 0;
+        }
+        self.validators[block_number as usize % self.validators.len()].id
+    }
+
+    pub fn state_root(&self) -> Hash {
+        self.ledger.state_root()
+    }
+
+    // Proof of `account`'s balance against the state root in the tip block.
+    pub fn prove_balance(&self, account: &str) -> BalanceProof {
+        self.ledger.prove_balance(account)
+    }
+
+    // Run the batch through the shard's validation rules. Rejected transactions
+    // are marked failed with their reason and the rest go on to the block.
+    fn reject_invalid_transactions(&mut self, transactions: Vec<Transaction>, block: BlockRef) -> Vec<Transaction> {
+        let mut accepted = Vec::with_capacity(transactions.len());
+        let mut batch_ids = HashSet::new();
+
+        for tx in transactions {
+            let context = ValidationContext {
+                shard_id: self.id,
+                ledger: &self.ledger,
+                processed_transactions: &self.processed_transactions,
+                tx_index: &self.tx_index,
+                batch_ids: &batch_ids,
+            };
+            match self.transaction_validator.validate(&tx, &context) {
+                Ok(()) => {
+                    batch_ids.insert(tx.id.clone());
+                    accepted.push(tx);
+                }
+                Err(reason) => {
+                    println!("Shard {}: Rejected transaction {}: {:?}", self.id, tx.id, reason);
+                    self.transaction_pool.remove(&tx.id);
+                    self.fail(tx, FailureReason::Rejected(reason), Some(block));
+                }
+            }
+        }
+        accepted
+    }
+
+    // Apply the batch to `ledger` in order. Transactions still waiting on an
+    // earlier nonce go back to the pool; those the ledger cannot apply, such
+    // as overdrafts, are marked failed. Neither goes into the block.
+    // Returns the transactions that executed and the fees they paid.
+    fn execute_transactions(
+        &mut self,
+        ledger: &mut Ledger,
+        transactions: Vec<Transaction>,
+        block: BlockRef,
+    ) -> (Vec<Transaction>, u64) {
+        let mut executed = Vec::with_capacity(transactions.len());
+        let mut fees: u64 = 0;
+        for mut tx in transactions {
+            match ledger.apply(&tx, self.id) {
+                Ok(fee) => {
+                    fees = fees.saturating_add(fee);
+                    executed.push(tx);
+                }
+                Err(ExecutionError::NonceGap { .. }) => {
+                    // stays pooled
+                    self.set_status(&mut tx, TransactionStatus::Pending, StatusReason::NonceGap, Some(block));
+                }
+                Err(error) => {
+                    println!("Shard {}: Transaction {} failed: {:?}", self.id, tx.id, error);
+                    self.transaction_pool.remove(&tx.id);
+                    self.fail(tx, FailureReason::Execution(error), Some(block));
+                }
+            }
+        }
+        (executed, fees)
+    }
+
+    // Pay a block's fees to its proposer and to this shard's validators.
+    fn pay_fees(&self, ledger: &mut Ledger, proposer: usize, fees: u64) -> Result<(), ExecutionError> {
+        let mut validators: Vec<&Validator> = self.validators.iter().collect();
+        validators.sort_by_key(|validator| validator.id);
+        let accounts: Vec<&str> = validators.iter().map(|validator| validator.account.as_str()).collect();
+        let proposer_account = validators
+            .iter()
+            .find(|validator| validator.id == proposer)
+            .map(|validator| validator.account.as_str());
+        ledger.pay_fees(fees, proposer_account, &accounts)
+    }
+
+    // Up to a block's worth of pooled transactions, highest fee per byte
+    // first and in arrival order among equals. They leave the pool once they
+    // are in a block or have failed. While the outbox of cross-shard
+    // transfers is full, no more outbound transfers are taken.
+    fn select_transactions(&self) -> Vec<Transaction> {
+        let mut outbound_room = self
+            .transaction_pool
+            .config()
+            .max_outbound
+            .saturating_sub(self.pending_cross_shard_txs.len());
+        let mut selected = self.transaction_pool.by_priority(self.transaction_pool.len());
+        selected.retain(|tx| {
+            if tx.to_shard == self.id {
+                return true;
+            }
+            let fits = outbound_room > 0;
+            outbound_room = outbound_room.saturating_sub(1);
+            fits
+        });
+        selected.truncate(self.max_transactions_per_block);
+        selected
+    }
+
+    // Run the block's transactions against a copy of `ledger` and pay out
+    // their fees. Every one of them must apply, the balances must still add
+    // up to the supply, and the result must match the state root in the header.
+    fn execute_block(&self, ledger: &Ledger, block: &Block) -> Result<Ledger, AppendError> {
+        let mut ledger = ledger.clone();
+        let mut fees: u64 = 0;
+        for tx in &block.transactions {
+            let fee = ledger.apply(tx, self.id).map_err(|error| AppendError::Execution {
+                tx_id: tx.id.clone(),
+                error,
+            })?;
+            fees = fees.saturating_add(fee);
+        }
+        self.pay_fees(&mut ledger, block.header.proposer, fees)
+            .map_err(AppendError::Fees)?;
+        ledger.check_supply().map_err(AppendError::SupplyInvariant)?;
+
+        let state_root = ledger.state_root();
+        if state_root != block.header.state_root {
+            return Err(AppendError::StateRootMismatch {
+                expected: state_root,
+                found: block.header.state_root,
+            });
+        }
+        Ok(ledger)
+    }
+
+    // What this shard requires of every block it takes.
+    fn block_rules(&self) -> BlockRules {
+        BlockRules {
+            shard_id: self.id,
+            max_transactions: self.max_transactions_per_block,
+            max_hashes_per_entry: self.poh_config.max_hashes_per_entry(),
+        }
+    }
+
+    // The block the next one has to extend.
+    pub fn tip(&self) -> ChainTip {
+        self.blocks.last().map(ChainTip::of).unwrap_or(self.base)
+    }
+
+    // Hash of the last PoH entry committed to this shard's chain.
+    pub fn poh_head(&self) -> Hash {
+        self.tip().poh_end_hash
+    }
+
+    // Proof that `first_tx` was recorded before `second_tx` on this shard's
+    // chain, carrying every entry from the one holding `first_tx` to the one
+    // holding `second_tx`.
+    pub fn prove_elapsed(&self, first_tx: &str, second_tx: &str) -> Result<ElapsedProof, &'static str> {
+        let entries: Vec<&PohEntry> = self.blocks.iter().flat_map(|block| &block.poh_entries).collect();
+        let find = |tx_id: &str| {
+            entries
+                .iter()
+                .position(|entry| entry.transactions.iter().any(|tx| tx == tx_id))
+        };
+
+        let first = find(first_tx).ok_or("First transaction not found in shard history")?;
+        let second = find(second_tx).ok_or("Second transaction not found in shard history")?;
+        let in_order = first < second
+            || (first == second && {
+                let txs = &entries[first].transactions;
+                txs.iter().position(|tx| tx == first_tx) < txs.iter().position(|tx| tx == second_tx)
+            });
+        if !in_order {
+            return Err("First transaction was not recorded before the second");
+        }
+
+        let start_hash = if first == 0 {
+            self.base.poh_end_hash
+        } else {
+            entries[first - 1].hash
+        };
+        let segment = entries[first..=second].iter().map(|entry| (*entry).clone()).collect();
+        Ok(ElapsedProof::new(first_tx, second_tx, start_hash, segment))
+    }
+
+    // The only way blocks enter the chain: the block must verify on its own
+    // and extend a known block, whether it was built here or by a peer.
+    // Extending the current tip, its transactions are executed and the block
+    // is written to the block store, if there is one, before it becomes part
+    // of the in-memory chain. A block on another branch is only kept in the
+    // tree, unless the fork-choice rule now prefers that branch.
+    pub fn append_block(&mut self, block: Block) -> Result<(), AppendError> {
+        if block.header.previous_hash != self.tip().block_hash {
+            return self.add_fork_block(block);
+        }
+        block.verify(&self.tip(), &self.block_rules())?;
+        let ledger = self.execute_block(&self.ledger, &block)?;
+        self.tx_index.insert_block(&block)?;
+        if let Some(store) = self.block_store.as_mut() {
+            if let Err(e) = store.append(&block) {
+                self.tx_index.remove_block(&block);
+                return Err(AppendError::Storage(e));
+            }
+        }
+        self.ledger = ledger;
+        self.tree.insert(block.clone(), self.block_weight(&block));
+        self.reset_poh(block.header.poh_end_hash);
+        self.pending_headers.push(block.header.clone());
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    fn add_fork_block(&mut self, block: Block) -> Result<(), AppendError> {
+        let parent = block.header.previous_hash;
+        let parent_tip = self.tree.tip_of(&parent).ok_or(AppendError::UnknownParent(parent))?;
+        block.verify(&parent_tip, &self.block_rules())?;
+
+        let block_number = block.header.block_number;
+        let weight = self.block_weight(&block);
+        self.pending_headers.push(block.header.clone());
+        self.tree.insert(block, weight);
+        println!("Shard {}: Added block #{} on a competing branch", self.id, block_number);
+
+        match self.tree.head() {
+            Some(head) if head != self.tip().block_hash => self.reorg(head),
+            _ => Ok(()),
+        }
+    }
+
+    // What a block counts for under the fork-choice rule: its proposer's vote
+    // weight in the block's epoch.
+    fn block_weight(&self, block: &Block) -> f64 {
+        self.validators
+            .iter()
+            .find(|validator| validator.id == block.header.proposer)
+            .map_or(0.0, |validator| validator.get_final_vote_weight(block.header.epoch))
+    }
+
+    // Switch the chain to the branch ending at `head`. The branch is executed
+    // from the base ledger first; if one of its blocks fails, that block and
+    // its descendants are dropped and the current chain stays.
+    fn reorg(&mut self, head: Hash) -> Result<(), AppendError> {
+        let branch: Vec<Block> = self.tree.chain_to(&head).into_iter().cloned().collect();
+        let common = self
+            .blocks
+            .iter()
+            .zip(&branch)
+            .take_while(|(ours, theirs)| ours.block_hash == theirs.block_hash)
+            .count();
+
+        let mut ledger = self.base_ledger.clone();
+        for block in &branch {
+            ledger = match self.execute_block(&ledger, block) {
+                Ok(ledger) => ledger,
+                Err(e) => {
+                    println!(
+                        "Shard {}: Block #{} on the preferred branch failed to execute ({}). Dropping the branch from it.",
+                        self.id, block.header.block_number, e
+                    );
+                    self.tree.remove(&block.block_hash);
+                    return Err(e);
+                }
+            };
+        }
+
+        // an id can only be on the chain once
+        if let Some((block, duplicate)) = self.tx_index.find_duplicate(&branch[common..], &self.blocks[common..]) {
+            println!(
+                "Shard {}: Block #{} on the preferred branch repeats transaction {}. Dropping the branch from it.",
+                self.id, block.header.block_number, duplicate.tx_id
+            );
+            self.tree.remove(&block.block_hash);
+            return Err(AppendError::DuplicateTransaction(duplicate));
+        }
+
+        // no snapshot may outlive the blocks it was taken at
+        let fork_height = self.base.block_number + common as u64;
+        if let Some(snapshots) = &self.snapshot_store {
+            snapshots.remove_above(fork_height).map_err(AppendError::Storage)?;
+        }
+        if let Some(store) = self.block_store.as_mut() {
+            store.truncate_from(fork_height + 1).map_err(AppendError::Storage)?;
+            for block in &branch[common..] {
+                store.append(block).map_err(AppendError::Storage)?;
+            }
+        }
+
+        let orphaned = self.blocks.split_off(common);
+        let adopted = branch[common..].to_vec();
+        self.blocks = branch;
+        self.ledger = ledger;
+        self.reset_poh(self.poh_head());
+        println!(
+            "Shard {}: Reorganized to block #{}, orphaning {} blocks",
+            self.id,
+            self.tip().block_number,
+            orphaned.len()
+        );
+        self.restore_orphaned(orphaned, &adopted);
+        Ok(())
+    }
+
+    // Transactions from orphaned blocks that the new branch did not include
+    // are pending again and go back to the pool. Those the new branch did
+    // include are settled, and leave the pool if they were waiting there.
+    fn restore_orphaned(&mut self, orphaned: Vec<Block>, adopted: &[Block]) {
+        let adopted_txs: Vec<&Transaction> = adopted.iter().flat_map(|block| &block.transactions).collect();
+        let adopted_ids: HashSet<&String> = adopted_txs.iter().map(|tx| &tx.id).collect();
+        let mut orphaned_ids = HashSet::new();
+
+        for block in orphaned {
+            self.tx_index.remove_block(&block);
+            let orphaned_from = BlockRef::built(block.header.block_number, block.block_hash);
+            for mut tx in block.transactions {
+                orphaned_ids.insert(tx.id.clone());
+                if adopted_ids.contains(&tx.id) {
+                    continue;
+                }
+                self.processed_transactions.remove(&tx.id);
+                self.pending_cross_shard_txs.retain(|pending| pending.transaction.id != tx.id);
+                self.transaction_count = self.transaction_count.saturating_sub(1);
+                self.set_status(&mut tx, TransactionStatus::Pending, StatusReason::Orphaned, Some(orphaned_from));
+                self.requeue(tx);
+            }
+        }
+
+        for block in adopted {
+            // the reorg checked the branch for ids already on the chain
+            if let Err(duplicate) = self.tx_index.insert_block(block) {
+                println!("Shard {}: Block #{} repeats {:?}", self.id, block.header.block_number, duplicate);
+            }
+        }
+        for (block, tx) in adopted.iter().flat_map(|block| block.transactions.iter().map(move |tx| (block, tx))) {
+            let mut settled = tx.clone();
+            let included = BlockRef::built(block.header.block_number, block.block_hash);
+            self.set_status(&mut settled, TransactionStatus::Completed, StatusReason::Included, Some(included));
+            self.transaction_pool.remove(&tx.id);
+            self.processed_transactions.insert(tx.id.clone());
+        }
+        for block in adopted {
+            let unsent: Vec<&Transaction> =
+                block.transactions.iter().filter(|tx| !orphaned_ids.contains(&tx.id)).collect();
+            self.queue_outbound_from(block, unsent);
+        }
+    }
+
+    // `block` has just extended the chain: its transactions are completed and
+    // leave the pool. The debits are final here, so the transfers to other
+    // shards go in the outbox for those shards to credit.
+    fn include_transactions(&mut self, block: &Block) {
+        let included = BlockRef::built(block.header.block_number, block.block_hash);
+        for tx in &block.transactions {
+            let mut tx = tx.clone();
+            self.transaction_pool.remove(&tx.id);
+            self.set_status(&mut tx, TransactionStatus::Completed, StatusReason::Included, Some(included));
+            self.processed_transactions.insert(tx.id.clone());
+            self.transaction_count += 1;
+            println!("Transaction {} status updated to Completed.", tx.id);
+        }
+        self.queue_outbound(block);
+    }
+
+    // Put the transfers `block` debits for other shards in the outbox, each
+    // with its proof from the block.
+    fn queue_outbound(&mut self, block: &Block) {
+        self.queue_outbound_from(block, block.transactions.iter().collect());
+    }
+
+    fn queue_outbound_from(&mut self, block: &Block, transactions: Vec<&Transaction>) {
+        for tx in transactions.into_iter().filter(|tx| tx.to_shard != self.id) {
+            if let Some(debit_proof) = block.prove_transaction(&tx.id) {
+                self.pending_cross_shard_txs.push(CrossShardTransfer {
+                    transaction: tx.clone(),
+                    debit_proof,
+                });
             }
         }
     }
 
-    pub fn validate_block_with_validators(&mut self, block: &Block) -> bool {
+    // Append a block produced by another node. If it extends the tip its
+    // transactions are settled here as they would be for a block built here;
+    // a block that brings a reorg has them settled by the reorg.
+    pub fn receive_block(&mut self, block: Block) -> Result<(), AppendError> {
+        let block_number = block.header.block_number;
+        let extends_tip = block.header.previous_hash == self.tip().block_hash;
+        self.append_block(block.clone())?;
+        if extends_tip {
+            self.include_transactions(&block);
+        }
+        println!("Shard {}: Accepted block #{} from peer", self.id, block_number);
+        Ok(())
+    }
+
+    pub fn validate_block_with_validators(&mut self, _block: &Block) -> bool {
         let mut total_weight = 0.0;
         let mut positive_weight = 0.0;
         let current_epoch = self.epoch;
@@ -324,26 +1503,103 @@ impl Shard {
         positive_weight > (total_weight * 0.3)
     }
 
-    pub fn drain_pending_checkpoint(&mut self) -> Option<Checkpoint> {
-        self.pending_checkpoint.take()
-    }
-
+    // A checkpoint of this shard from a peer finalizes its block. It is only
+    // taken for a block already in the tree whose state root matches the
+    // checkpoint's state. If that block is on another branch the chain
+    // switches to it first, executing the branch as any reorg does; then
+    // everything up to it is settled and the tree is rerooted there.
+    // A checkpoint of another shard only settles which of its blocks debit
+    // proofs are checked against.
     pub fn receive_checkpoint(&mut self, checkpoint: Checkpoint) {
         println!("Shard {}: Received checkpoint from Shard {}", self.id, checkpoint.shard_id);
-        self.ledger = checkpoint.ledger_snapshot;
-        self.processed_transactions = checkpoint.processed_transactions_snapshot;
-        self.transaction_pool = checkpoint.transaction_pool_snapshot;
-        self.blocks.clear();
+        if checkpoint.shard_id != self.id {
+            self.remote_chains.add_checkpoint(checkpoint.shard_id, &checkpoint.tip());
+            return;
+        }
+        if checkpoint.block_height <= self.base.block_number {
+            return;
+        }
+
+        let ledger = checkpoint.ledger();
+        let tip = checkpoint.tip();
+        let block_state_root = match self.tree.get(&tip.block_hash) {
+            Some(block) if block.header.block_number == tip.block_number => block.header.state_root,
+            _ => {
+                println!("Shard {}: Checkpoint at unknown block #{}, ignoring", self.id, tip.block_number);
+                return;
+            }
+        };
+        if block_state_root != ledger.state_root() || ledger.check_supply().is_err() {
+            println!("Shard {}: Checkpoint state does not match block #{}, ignoring", self.id, tip.block_number);
+            return;
+        }
+
+        if !self.blocks.iter().any(|block| block.block_hash == tip.block_hash) {
+            if let Err(e) = self.reorg(tip.block_hash) {
+                println!("Shard {}: Could not switch to checkpoint block #{} ({}), ignoring", self.id, tip.block_number, e);
+                return;
+            }
+        }
+        let index = self
+            .blocks
+            .iter()
+            .position(|block| block.block_hash == tip.block_hash)
+            .expect("checkpoint block is on the chain after the switch");
+        self.settle(index, ledger);
+        self.last_checkpoint = Some(checkpoint);
+
+        // a branch built on the checkpoint block may outweigh our own
+        match self.tree.head() {
+            Some(head) if head != self.tip().block_hash => {
+                if let Err(e) = self.reorg(head) {
+                    println!("Shard {}: Staying on block #{} ({})", self.id, self.tip().block_number, e);
+                }
+            }
+            _ => {}
+        }
     }
 
-    pub fn capture_checkpoint(&self) -> Checkpoint {
-        Checkpoint::new(
+    // Settle the chain at its newest final block: snapshot the state there,
+    // if the shard has storage, and make that block the base. Returns the
+    // checkpoint, or None while no block is final yet.
+    pub fn finalize(&mut self) -> Option<Checkpoint> {
+        let index = self.blocks.len().checked_sub(FINALITY_DEPTH + 1)?;
+        let replayed = self.blocks[..=index]
+            .iter()
+            .try_fold(self.base_ledger.clone(), |ledger, block| self.execute_block(&ledger, block));
+        let ledger = match replayed {
+            Ok(ledger) => ledger,
+            Err(e) => {
+                eprintln!("Shard {}: Failed to replay the chain up to its final block: {}", self.id, e);
+                return None;
+            }
+        };
+
+        let checkpoint = Checkpoint::new(
             self.id,
-            self.blocks.len() as u64,
-            self.ledger.clone(),
-            self.transaction_pool.clone(),
+            ChainTip::of(&self.blocks[index]),
+            &ledger,
+            self.transaction_pool.iter().cloned().collect(),
             self.processed_transactions.clone(),
-        )
+        );
+        if let Err(e) = self.save_snapshot(&checkpoint) {
+            eprintln!("Shard {}: Failed to save snapshot: {}", self.id, e);
+        }
+        self.settle(index, ledger);
+        self.last_checkpoint = Some(checkpoint.clone());
+        Some(checkpoint)
+    }
+
+    // Make `self.blocks[index]`, with `ledger` as the state there, the new
+    // base. Earlier blocks leave memory, though the block store and the
+    // transaction index keep them, and the tree drops every branch that does
+    // not descend from it.
+    fn settle(&mut self, index: usize, ledger: Ledger) {
+        let tip = ChainTip::of(&self.blocks[index]);
+        self.blocks.drain(..=index);
+        self.tree.reroot(&tip.block_hash);
+        self.base = tip;
+        self.base_ledger = ledger;
     }
 
     pub fn check_epoch_transition(&self) -> bool {
@@ -363,9 +1619,6 @@ impl Shard {
         for validator in &mut self.validators {
             validator.epochs_active += 1;
         }
-
-        self.transaction_pool.clear();
-        self.processed_transactions.clear();
     }
 
     fn recalculate_validator_rankings(&mut self) {
@@ -382,3 +1635,426 @@ impl Shard {
         println!("Dynamic assignment of validators for Shard {} at Epoch {}", self.id, self.epoch);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poh::clock::{Clock, MockClock};
+    use chrono::{DateTime, TimeZone};
+    use std::sync::Arc;
+
+    const SLOT: Duration = Duration::from_millis(400);
+
+    fn mock_clock() -> Arc<MockClock> {
+        Arc::new(MockClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap()))
+    }
+
+    fn mock_shard(clock: &Arc<MockClock>) -> Shard {
+        mock_shard_with_id(clock, 1)
+    }
+
+    // Two ticks of four hashes to a 400 ms slot.
+    fn mock_shard_with_id(clock: &Arc<MockClock>, id: usize) -> Shard {
+        let poh_config = PohConfig {
+            hashes_per_tick: 4,
+            ticks_per_slot: 2,
+            target_slot_ms: 400,
+            measured_hashes_per_second: 0.0,
+        };
+        let validators = vec![Validator::new(0, 1, 0.9), Validator::new(1, 1, 0.9)];
+        Shard::with_poh_config(id, 10, 100, validators, poh_config, clock.clone())
+    }
+
+    // A transfer of `amount` signed by the test key for `sender`, which
+    // sends from shard 1.
+    fn transfer(sender: &str, id: &str, amount: u64, nonce: u64) -> Transaction {
+        let keypair = keys::test_keypair(sender);
+        let mut tx = Transaction {
+            id: id.to_string(),
+            from_account: keypair.address(),
+            to_account: "bob".to_string(),
+            amount,
+            fee: 1,
+            nonce,
+            from_shard: 1,
+            to_shard: 1,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Pending,
+        };
+        tx.sign(&keypair);
+        tx
+    }
+
+    fn fund(shard: &mut Shard, sender: &str) {
+        shard.fund_account(&keys::test_keypair(sender).address(), 1_000).unwrap();
+    }
+
+    fn rejection(shard: &Shard, tx_id: &str) -> Option<RejectionReason> {
+        match shard.current_status(tx_id) {
+            Some(TransactionStatus::Failed(FailureReason::Rejected(reason))) => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn every_slot_gets_a_block_even_without_transactions() {
+        let clock = mock_clock();
+        let mut shard = mock_shard(&clock);
+        shard.check_and_create_block();
+        assert!(shard.blocks.is_empty());
+
+        for slot in 1..=3 {
+            clock.advance(SLOT);
+            shard.check_and_create_block();
+            assert_eq!(shard.current_slot(), slot);
+            assert_eq!(shard.blocks.len(), slot as usize);
+        }
+        for block in &shard.blocks {
+            assert!(block.transactions.is_empty());
+            assert_eq!(block.poh_entries.iter().filter(|entry| entry.is_tick()).count(), 2);
+        }
+    }
+
+    #[test]
+    fn rejected_transactions_fail_with_their_reason_and_the_rest_go_in() {
+        let clock = mock_clock();
+        let mut shard = mock_shard(&clock);
+        shard.register_transaction_rule(Box::new(validation::AmountBounds { min: 1, max: 100 }));
+        for sender in ["alice", "carol"] {
+            fund(&mut shard, sender);
+        }
+
+        let mut oversized = transfer("dave", "oversized", 10, 0);
+        oversized.to_account = "x".repeat(validation::DEFAULT_MAX_TRANSACTION_BYTES);
+        oversized.sign(&keys::test_keypair("dave"));
+        let batch = vec![
+            transfer("alice", "alice-0", 10, 0),
+            oversized,
+            transfer("erin", "bad id!", 10, 0),
+            transfer("frank", "zero", 0, 0),
+            transfer("grace", "large", 500, 0),
+            transfer("carol", "carol-0", 10, 0),
+        ];
+        let results = shard.process_transactions(batch);
+        assert!(results.iter().all(Result::is_ok), "{:?}", results);
+
+        clock.advance(SLOT);
+        shard.check_and_create_block();
+
+        let included: Vec<&str> = shard.blocks[0].transactions.iter().map(|tx| tx.id.as_str()).collect();
+        assert_eq!(included, vec!["alice-0", "carol-0"]);
+        assert!(matches!(rejection(&shard, "oversized"), Some(RejectionReason::TooLarge { .. })));
+        assert_eq!(rejection(&shard, "bad id!"), Some(RejectionReason::InvalidId("bad id!".to_string())));
+        assert_eq!(
+            rejection(&shard, "zero"),
+            Some(RejectionReason::AmountOutOfBounds {
+                amount: 0,
+                min: validation::DEFAULT_MIN_AMOUNT,
+                max: validation::DEFAULT_MAX_AMOUNT,
+            })
+        );
+        assert_eq!(
+            rejection(&shard, "large"),
+            Some(RejectionReason::AmountOutOfBounds { amount: 500, min: 1, max: 100 })
+        );
+        assert_eq!(shard.get_transaction_pool().len(), 0);
+    }
+
+    #[test]
+    fn blocks_that_fail_verification_are_refused() {
+        let clock = mock_clock();
+        let mut producer = mock_shard(&clock);
+        clock.advance(SLOT);
+        producer.check_and_create_block();
+        let block = producer.blocks[0].clone();
+
+        let mut follower = mock_shard(&clock);
+        let mut unsealed = block.clone();
+        unsealed.header.timestamp += 1;
+        assert!(matches!(
+            follower.append_block(unsealed),
+            Err(AppendError::Invalid(BlockError::HashMismatch { .. }))
+        ));
+        let mut retimed = block.clone();
+        retimed.poh_entries[0].num_hashes += 1;
+        assert!(matches!(follower.append_block(retimed), Err(AppendError::Invalid(BlockError::Poh(_)))));
+        assert!(follower.blocks.is_empty());
+
+        follower.append_block(block.clone()).unwrap();
+        assert_eq!(follower.tip(), producer.tip());
+    }
+
+    #[test]
+    fn restarts_from_the_newest_snapshot_and_the_blocks_after_it() {
+        let dir = std::env::temp_dir().join(format!("pohts-shard-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let clock = mock_clock();
+        let mut shard = mock_shard(&clock);
+        shard.open_storage(&dir, 2).unwrap();
+        for _ in 0..FINALITY_DEPTH + 3 {
+            clock.advance(SLOT);
+            shard.check_and_create_block();
+        }
+        let checkpoint = shard.finalize().unwrap();
+        assert_eq!(checkpoint.block_height, 3);
+        let tip = shard.tip();
+
+        let mut restarted = mock_shard(&clock);
+        restarted.open_storage(&dir, 2).unwrap();
+        assert_eq!(restarted.base, checkpoint.tip());
+        assert_eq!(restarted.blocks.len(), FINALITY_DEPTH);
+        assert_eq!(restarted.tip(), tip);
+        assert_eq!(restarted.state_root(), shard.state_root());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn status_history_follows_the_lifecycle() {
+        let clock = mock_clock();
+        let mut shard = mock_shard(&clock);
+        fund(&mut shard, "alice");
+        let results = shard.process_transactions(vec![
+            transfer("alice", "alice-0", 10, 0),
+            transfer("alice", "alice-1", 2_000, 1),
+        ]);
+        assert!(results.iter().all(Result::is_ok), "{:?}", results);
+        clock.advance(SLOT);
+        shard.check_and_create_block();
+
+        let block = Some(BlockRef::built(1, shard.blocks[0].block_hash));
+        let reasons = |tx_id: &str| -> Vec<(StatusReason, Option<u64>)> {
+            let history = shard.get_status_index().history(tx_id).unwrap();
+            history.iter().map(|change| (change.reason.clone(), change.block.map(|b| b.block_number))).collect()
+        };
+        assert_eq!(
+            reasons("alice-0"),
+            vec![
+                (StatusReason::Admitted, None),
+                (StatusReason::Selected, Some(1)),
+                (StatusReason::Included, Some(1)),
+            ]
+        );
+        assert_eq!(shard.status_index.current("alice-0").unwrap().block, block);
+
+        let overdraft = shard.status_index.current("alice-1").unwrap();
+        assert!(matches!(
+            overdraft.status,
+            TransactionStatus::Failed(FailureReason::Execution(ExecutionError::Overdraft { .. }))
+        ));
+        assert_eq!(overdraft.at, clock.now());
+        assert!(shard.get_failed_transactions().get("alice-1").is_some());
+    }
+
+    #[test]
+    fn credits_need_a_debit_in_a_block_the_sending_shard_announced() {
+        let clock = mock_clock();
+        let mut sender = mock_shard(&clock);
+        let mut receiver = mock_shard_with_id(&clock, 2);
+        fund(&mut sender, "alice");
+        let mut tx = transfer("alice", "alice-0", 10, 0);
+        tx.to_shard = 2;
+        tx.sign(&keys::test_keypair("alice"));
+        assert_eq!(sender.process_transactions(vec![tx.clone()]), vec![Ok(())]);
+        clock.advance(SLOT);
+        sender.check_and_create_block();
+        let mut sent = sender.drain_pending_cross_shard_txs();
+        assert_eq!(sent.len(), 1);
+        let real = CrossShardTransfer::from_bytes(&sent.pop().unwrap().to_bytes()).unwrap();
+
+        // a block made up to hold the transaction proves nothing
+        let header = BlockHeader::new(1, Hash::default(), 1, 0, 0, Hash::default(), 0);
+        let made_up = Block::new(header, Hash::default(), Vec::new(), vec![tx]);
+        let forged = CrossShardTransfer {
+            transaction: real.transaction.clone(),
+            debit_proof: made_up.prove_transaction("alice-0").unwrap(),
+        };
+        let unproven = Err(AdmissionError::Rejected(RejectionReason::UnprovenDebit));
+        assert_eq!(receiver.process_cross_shard_transaction(forged), unproven);
+        // nor does the real block, until the sending shard announces it
+        assert_eq!(receiver.process_cross_shard_transaction(real.clone()), unproven);
+
+        // a header has to extend a block already trusted
+        let mut detached = sender.blocks[0].header.clone();
+        detached.previous_hash = Hash::digest(b"unknown");
+        assert!(!receiver.receive_header(&detached));
+        for header in sender.drain_pending_headers() {
+            assert!(receiver.receive_header(&header));
+        }
+        assert_eq!(receiver.process_cross_shard_transaction(real), Ok(()));
+        assert_eq!(receiver.current_status("alice-0"), Some(TransactionStatus::Completed));
+        assert_eq!(receiver.ledger.balance("bob"), 10);
+    }
+
+    #[test]
+    fn pooled_transactions_carry_over_into_the_next_epoch() {
+        let clock = mock_clock();
+        let mut shard = mock_shard(&clock);
+        fund(&mut shard, "alice");
+        assert_eq!(shard.process_transactions(vec![transfer("alice", "alice-0", 10, 0)]), vec![Ok(())]);
+        clock.advance(SLOT);
+        shard.check_and_create_block();
+        assert_eq!(shard.process_transactions(vec![transfer("alice", "alice-1", 10, 1)]), vec![Ok(())]);
+
+        shard.transition_to_next_epoch();
+        assert_eq!(shard.epoch, 1);
+        assert!(shard.get_transaction_pool().get("alice-1").is_some());
+        // what is already on the chain stays known, so it cannot come in again
+        assert_eq!(
+            shard.process_transactions(vec![transfer("alice", "alice-0", 10, 0)]),
+            vec![Err(AdmissionError::Duplicate)]
+        );
+
+        clock.advance(SLOT);
+        shard.check_and_create_block();
+        assert_eq!(shard.current_status("alice-1"), Some(TransactionStatus::Completed));
+    }
+
+    // `ours` builds block 1 holding carol-0 while `peer`, on the same
+    // genesis, builds 1' holding alice-0 and 2' holding alice-1.
+    fn competing_branches(clock: &Arc<MockClock>) -> (Shard, Shard) {
+        let mut ours = mock_shard(clock);
+        let mut peer = mock_shard(clock);
+        for shard in [&mut ours, &mut peer] {
+            fund(shard, "alice");
+            fund(shard, "carol");
+        }
+        assert_eq!(ours.process_transactions(vec![transfer("carol", "carol-0", 10, 0)]), vec![Ok(())]);
+        assert_eq!(peer.process_transactions(vec![transfer("alice", "alice-0", 10, 0)]), vec![Ok(())]);
+        clock.advance(SLOT);
+        ours.check_and_create_block();
+        peer.check_and_create_block();
+        assert_eq!(peer.process_transactions(vec![transfer("alice", "alice-1", 10, 1)]), vec![Ok(())]);
+        clock.advance(SLOT);
+        peer.check_and_create_block();
+        assert_eq!((ours.blocks.len(), peer.blocks.len()), (1, 2));
+        (ours, peer)
+    }
+
+    #[test]
+    fn a_heavier_branch_from_a_peer_takes_over_and_requeues_the_rest() {
+        let clock = mock_clock();
+        let (mut ours, peer) = competing_branches(&clock);
+        let orphaned = ours.blocks[0].block_hash;
+        for block in &peer.blocks {
+            ours.receive_block(block.clone()).unwrap();
+        }
+        assert_eq!(ours.tip(), peer.tip());
+        assert_eq!(ours.state_root(), peer.state_root());
+        assert_eq!(ours.current_status("alice-1"), Some(TransactionStatus::Completed));
+
+        // carol-0 is pending again, back in the pool
+        let status = ours.status_index.current("carol-0").unwrap();
+        assert_eq!(status.status, TransactionStatus::Pending);
+        assert_eq!(status.reason, StatusReason::Orphaned);
+        assert_eq!(status.block.unwrap().block_hash, Some(orphaned));
+        assert!(ours.get_transaction_pool().get("carol-0").is_some());
+        assert!(ours.transaction_receipt("carol-0").unwrap().location.is_none());
+
+        clock.advance(SLOT);
+        ours.check_and_create_block();
+        assert_eq!(ours.tip().block_number, 3);
+        assert_eq!(ours.current_status("carol-0"), Some(TransactionStatus::Completed));
+    }
+
+    #[test]
+    fn checkpoints_are_only_taken_for_known_blocks_with_their_state() {
+        let clock = mock_clock();
+        let (mut ours, peer) = competing_branches(&clock);
+        let block = &peer.blocks[0];
+        let mut ledger = Ledger::new();
+        for sender in ["alice", "carol"] {
+            ledger.mint(&keys::test_keypair(sender).address(), 1_000).unwrap();
+        }
+        ours.execute_transactions(&mut ledger, block.transactions.clone(), BlockRef::building(1));
+        let fees = block.transactions.iter().map(|tx| tx.fee).sum();
+        ours.pay_fees(&mut ledger, block.header.proposer, fees).unwrap();
+        assert_eq!(ledger.state_root(), block.header.state_root);
+        let checkpoint = |ledger: &Ledger| Checkpoint::new(1, ChainTip::of(block), ledger, Vec::new(), HashSet::new());
+
+        // the block is not known yet
+        ours.receive_checkpoint(checkpoint(&ledger));
+        assert!(ours.last_checkpoint.is_none());
+
+        ours.receive_block(block.clone()).unwrap();
+        let mut forged = ledger.clone();
+        forged.mint("mallory", 1_000).unwrap();
+        ours.receive_checkpoint(checkpoint(&forged));
+        assert!(ours.last_checkpoint.is_none());
+
+        ours.receive_checkpoint(checkpoint(&ledger));
+        assert_eq!(ours.last_checkpoint.as_ref().unwrap().block_hash, block.block_hash);
+        assert_eq!(ours.tip(), ChainTip::of(block));
+        assert_eq!(ours.state_root(), ledger.state_root());
+        assert_eq!(ours.current_status("carol-0"), Some(TransactionStatus::Pending));
+    }
+
+    // Drive a shard through a fixed workload on a mock clock starting at
+    // `start`, returning the hashes of the blocks it made.
+    fn replay(start: DateTime<Utc>) -> Vec<Hash> {
+        let clock = Arc::new(MockClock::new(start));
+        let mut shard = mock_shard(&clock);
+        fund(&mut shard, "alice");
+        for nonce in 0..6 {
+            let tx = transfer("alice", &format!("alice-{}", nonce), 10, nonce);
+            assert_eq!(shard.process_transactions(vec![tx]), vec![Ok(())]);
+            clock.advance(Duration::from_millis(250));
+            shard.check_and_create_block();
+        }
+        shard.blocks.iter().map(|block| block.block_hash).collect()
+    }
+
+    #[test]
+    fn replay_on_a_mock_clock_is_deterministic() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let hashes = replay(start);
+        assert!(hashes.len() >= 3, "only {} blocks", hashes.len());
+        assert_eq!(replay(start), hashes);
+
+        let later = replay(start + chrono::Duration::seconds(1));
+        assert_eq!(later.len(), hashes.len());
+        assert_ne!(later, hashes);
+    }
+
+    #[test]
+    fn transaction_round_trips_and_stays_signed() {
+        let tx = transfer("alice", "alice-3", 10, 3);
+        let decoded = Transaction::from_bytes(&tx.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), tx.to_bytes());
+        assert_eq!(decoded.verify_signature(), Ok(()));
+
+        let mut forged = decoded;
+        forged.amount += 1;
+        assert!(forged.verify_signature().is_err());
+    }
+
+    #[test]
+    fn checkpoint_and_transfer_round_trip() {
+        let mut ledger = Ledger::new();
+        ledger.mint("alice", 500).unwrap();
+        let tip = ChainTip {
+            block_number: 7,
+            block_hash: Hash::digest(b"block"),
+            poh_end_hash: Hash::digest(b"poh"),
+        };
+        let processed = HashSet::from(["alice-0".to_string()]);
+        let checkpoint = Checkpoint::new(2, tip, &ledger, vec![transfer("alice", "alice-1", 10, 1)], processed);
+        let decoded = Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), checkpoint.to_bytes());
+        assert_eq!(decoded.tip(), tip);
+        assert_eq!(decoded.ledger().state_root(), ledger.state_root());
+
+        let tx = transfer("alice", "alice-0", 10, 0);
+        let header = BlockHeader::new(1, Hash::default(), 1, 0, 0, Hash::default(), 0);
+        let block = Block::new(header, Hash::default(), Vec::new(), vec![tx.clone()]);
+        let transfer = CrossShardTransfer {
+            transaction: tx,
+            debit_proof: block.prove_transaction("alice-0").unwrap(),
+        };
+        let decoded = CrossShardTransfer::from_bytes(&transfer.to_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes(), transfer.to_bytes());
+        let mut trusted = RemoteChains::new();
+        assert!(trusted.add_header(&block.header));
+        assert!(decoded.is_proven(&trusted));
+    }
+}
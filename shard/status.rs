@@ -0,0 +1,242 @@
+use crate::poh::hash::Hash;
+use crate::shard::shard::{FailureReason, Transaction, TransactionStatus};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+
+pub const DEFAULT_SETTLED_HISTORY: usize = 100_000;
+pub const DEFAULT_FAILED_TRANSACTIONS: usize = 10_000;
+
+// The block a transition concerns. Its hash is only known once the block has
+// been built, so transitions made while building it carry just the number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockRef {
+    pub block_number: u64,
+    pub block_hash: Option<Hash>,
+}
+
+impl BlockRef {
+    pub fn building(block_number: u64) -> Self {
+        BlockRef {
+            block_number,
+            block_hash: None,
+        }
+    }
+
+    pub fn built(block_number: u64, block_hash: Hash) -> Self {
+        BlockRef {
+            block_number,
+            block_hash: Some(block_hash),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusReason {
+    Admitted,              // accepted into the pool
+    Selected,              // taken from the pool for a block being built
+    NonceGap,              // waits in the pool for the sender's earlier transactions
+    BlockRejected(String), // the block it was in was not accepted; back in the pool
+    Included,              // in a block on the chosen chain
+    Orphaned,              // its block left the chosen chain in a reorg; back in the pool
+    Failed(FailureReason),
+    TtlElapsed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusChange {
+    pub status: TransactionStatus,
+    pub reason: StatusReason,
+    pub at: DateTime<Utc>,
+    pub block: Option<BlockRef>,
+}
+
+// Completed, failed and expired transactions only change again if a reorg
+// puts them back in the pool.
+fn is_settled(status: &TransactionStatus) -> bool {
+    matches!(
+        status,
+        TransactionStatus::Completed | TransactionStatus::Failed(_) | TransactionStatus::Expired
+    )
+}
+
+// Every status a shard has given each transaction, oldest first. Kept apart
+// from the pool, so a transaction's history is still there after it has left.
+// Only the `capacity` most recently settled transactions are remembered; the
+// history of one still in play is never dropped.
+#[derive(Debug, Clone)]
+pub struct StatusIndex {
+    history: HashMap<String, Vec<StatusChange>>,
+    settled: VecDeque<(String, usize)>, // id and history length when it settled, oldest first
+    capacity: usize,
+}
+
+impl Default for StatusIndex {
+    fn default() -> Self {
+        StatusIndex::new(DEFAULT_SETTLED_HISTORY)
+    }
+}
+
+impl StatusIndex {
+    pub fn new(capacity: usize) -> Self {
+        StatusIndex {
+            history: HashMap::new(),
+            settled: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, tx_id: &str, change: StatusChange) {
+        let settled = is_settled(&change.status);
+        let changes = self.history.entry(tx_id.to_string()).or_default();
+        changes.push(change);
+        if settled {
+            self.settled.push_back((tx_id.to_string(), changes.len()));
+        }
+
+        while self.settled.len() > self.capacity {
+            let (oldest, len) = self.settled.pop_front().unwrap();
+            // a transaction that has changed since is either in play or queued again
+            if self.history.get(&oldest).is_some_and(|changes| changes.len() == len) {
+                self.history.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn history(&self, tx_id: &str) -> Option<&[StatusChange]> {
+        self.history.get(tx_id).map(Vec::as_slice)
+    }
+
+    pub fn current(&self, tx_id: &str) -> Option<&StatusChange> {
+        self.history.get(tx_id).and_then(|changes| changes.last())
+    }
+}
+
+// The most recent transactions that failed or expired on a shard, by id, up
+// to `capacity`; older ones are forgotten first.
+#[derive(Debug, Clone)]
+pub struct FailedTransactions {
+    by_id: HashMap<String, Transaction>,
+    order: VecDeque<String>, // oldest first
+    capacity: usize,
+}
+
+impl Default for FailedTransactions {
+    fn default() -> Self {
+        FailedTransactions::new(DEFAULT_FAILED_TRANSACTIONS)
+    }
+}
+
+impl FailedTransactions {
+    pub fn new(capacity: usize) -> Self {
+        FailedTransactions {
+            by_id: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // Keep `tx`, replacing an earlier failure with the same id.
+    pub fn insert(&mut self, tx: Transaction) {
+        if self.by_id.insert(tx.id.clone(), tx.clone()).is_some() {
+            self.order.retain(|id| *id != tx.id);
+        }
+        self.order.push_back(tx.id);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.by_id.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, tx_id: &str) -> Option<&Transaction> {
+        self.by_id.get(tx_id)
+    }
+
+    // Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.order.iter().map(|id| &self.by_id[id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn change(status: TransactionStatus, reason: StatusReason) -> StatusChange {
+        StatusChange {
+            status,
+            reason,
+            at: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            block: None,
+        }
+    }
+
+    fn complete(index: &mut StatusIndex, tx_id: &str) {
+        index.record(tx_id, change(TransactionStatus::Pending, StatusReason::Admitted));
+        index.record(tx_id, change(TransactionStatus::Completed, StatusReason::Included));
+    }
+
+    fn transaction(id: &str) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            from_account: "alice".to_string(),
+            to_account: "bob".to_string(),
+            amount: 10,
+            fee: 1,
+            nonce: 0,
+            from_shard: 1,
+            to_shard: 1,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Expired,
+        }
+    }
+
+    #[test]
+    fn forgets_the_oldest_settled_transactions_first() {
+        let mut index = StatusIndex::new(2);
+        index.record("waiting", change(TransactionStatus::Pending, StatusReason::Admitted));
+        for tx_id in ["a", "b", "c"] {
+            complete(&mut index, tx_id);
+        }
+        assert!(index.history("a").is_none());
+        assert_eq!(index.history("b").unwrap().len(), 2);
+        assert_eq!(index.current("c").unwrap().status, TransactionStatus::Completed);
+        // one still in play is kept however many settle after it
+        assert_eq!(index.current("waiting").unwrap().reason, StatusReason::Admitted);
+    }
+
+    #[test]
+    fn a_transaction_back_in_play_outlives_its_old_settlement() {
+        let mut index = StatusIndex::new(1);
+        complete(&mut index, "a");
+        index.record("a", change(TransactionStatus::Pending, StatusReason::Orphaned));
+        complete(&mut index, "b");
+
+        let history = index.history("a").unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].reason, StatusReason::Orphaned);
+
+        // once it settles again it ages out like any other
+        index.record("a", change(TransactionStatus::Completed, StatusReason::Included));
+        assert!(index.history("b").is_none());
+        complete(&mut index, "c");
+        assert!(index.history("a").is_none());
+    }
+
+    #[test]
+    fn keeps_the_most_recent_failures() {
+        let mut failed = FailedTransactions::new(2);
+        for tx_id in ["a", "b", "c"] {
+            failed.insert(transaction(tx_id));
+        }
+        assert!(failed.get("a").is_none());
+
+        // failing again makes it the newest
+        failed.insert(transaction("b"));
+        failed.insert(transaction("d"));
+        let ids: Vec<&str> = failed.iter().map(|tx| tx.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "d"]);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::block::block::{Block, InclusionProof};
+use crate::poh::merkle::MerkleProof;
+use crate::poh::hash::Hash;
+use crate::shard::shard::TransactionStatus;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+// Where a transaction sits on a shard's chain.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TransactionLocation {
+    pub shard_id: usize,
+    pub block_number: u64,
+    pub block_hash: Hash,
+    pub entry_index: usize, // which of the block's PoH entries records the id
+}
+
+// A block repeats a transaction id that is already on the chain, or that
+// appears twice in the block itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateTransaction {
+    pub tx_id: String,
+    pub location: TransactionLocation, // where the id is already indexed
+}
+
+// Every transaction on a shard's chain, by id. Covers blocks from before the
+// shard's base as well, so it keeps answering after a checkpoint; on startup
+// it is rebuilt from the block store.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionIndex {
+    locations: HashMap<String, TransactionLocation>,
+}
+
+impl TransactionIndex {
+    pub fn new() -> Self {
+        TransactionIndex::default()
+    }
+
+    pub fn get(&self, tx_id: &str) -> Option<&TransactionLocation> {
+        self.locations.get(tx_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    // Index the block's transactions. An id can only be on the chain once,
+    // so if one is already indexed, or repeats within the block, nothing is
+    // indexed and the duplicate is returned.
+    pub fn insert_block(&mut self, block: &Block) -> Result<(), DuplicateTransaction> {
+        if let Some((_, duplicate)) = self.find_duplicate(std::slice::from_ref(block), &[]) {
+            return Err(duplicate);
+        }
+        self.locations
+            .extend(locations_in(block).map(|(tx_id, location)| (tx_id.clone(), location)));
+        Ok(())
+    }
+
+    // The first id in `blocks` that is indexed under a block other than
+    // those in `replaced`, or that repeats within `blocks`, with the block
+    // it is in: the check for a branch about to replace `replaced`.
+    pub fn find_duplicate<'a>(
+        &self,
+        blocks: &'a [Block],
+        replaced: &[Block],
+    ) -> Option<(&'a Block, DuplicateTransaction)> {
+        let replaced: HashSet<&Hash> = replaced.iter().map(|block| &block.block_hash).collect();
+        let mut seen: HashMap<&String, TransactionLocation> = HashMap::new();
+        for block in blocks {
+            for (tx_id, location) in locations_in(block) {
+                let kept = self
+                    .locations
+                    .get(tx_id)
+                    .filter(|existing| !replaced.contains(&existing.block_hash));
+                if let Some(existing) = kept.or(seen.get(tx_id)) {
+                    let duplicate = DuplicateTransaction {
+                        tx_id: tx_id.clone(),
+                        location: *existing,
+                    };
+                    return Some((block, duplicate));
+                }
+                seen.insert(tx_id, location);
+            }
+        }
+        None
+    }
+
+    // Forget the block's transactions, unless they have since been indexed
+    // under another block.
+    pub fn remove_block(&mut self, block: &Block) {
+        for tx_id in block.poh_entries.iter().flat_map(|entry| &entry.transactions) {
+            if self.locations.get(tx_id).is_some_and(|location| location.block_hash == block.block_hash) {
+                self.locations.remove(tx_id);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.locations.clear();
+    }
+}
+
+// Each transaction id in `block` with where it sits.
+fn locations_in(block: &Block) -> impl Iterator<Item = (&String, TransactionLocation)> {
+    block.poh_entries.iter().enumerate().flat_map(move |(entry_index, entry)| {
+        entry.transactions.iter().map(move |tx_id| {
+            let location = TransactionLocation {
+                shard_id: block.header.shard_id,
+                block_number: block.header.block_number,
+                block_hash: block.block_hash,
+                entry_index,
+            };
+            (tx_id, location)
+        })
+    })
+}
+
+// What a shard knows about a transaction: where it stands and, once it is in
+// a block on the chain, where and the proof that it is there. `entry_proof`
+// proves the id against the transaction root of the entry that records it,
+// for clients following entries rather than blocks.
+#[derive(Debug, Clone)]
+pub struct TransactionReceipt {
+    pub tx_id: String,
+    pub shard_id: usize,
+    pub status: TransactionStatus,
+    pub location: Option<TransactionLocation>,
+    pub proof: Option<InclusionProof>,
+    pub entry_proof: Option<MerkleProof>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::block::BlockHeader;
+    use crate::poh::entry::PohEntry;
+    use chrono::Utc;
+
+    // A block recording each of `ids` in an entry of its own.
+    fn block(block_number: u64, ids: &[&str]) -> Block {
+        let entries = ids
+            .iter()
+            .map(|id| PohEntry::new(1, vec![id.to_string()], Vec::new(), Hash::default(), &Hash::default(), Utc::now()))
+            .collect();
+        let previous_hash = Hash::digest(&block_number.to_le_bytes());
+        let header = BlockHeader::new(block_number, previous_hash, 1, 0, 0, Hash::default(), 0);
+        Block::new(header, Hash::default(), entries, Vec::new())
+    }
+
+    #[test]
+    fn an_id_is_indexed_once() {
+        let mut index = TransactionIndex::new();
+        let first = block(1, &["tx1", "tx2"]);
+        index.insert_block(&first).unwrap();
+
+        let duplicate = index.insert_block(&block(2, &["tx3", "tx2"])).unwrap_err();
+        assert_eq!(duplicate.tx_id, "tx2");
+        assert_eq!(duplicate.location.block_hash, first.block_hash);
+        assert_eq!(duplicate.location.entry_index, 1);
+        assert!(index.get("tx3").is_none());
+
+        let repeated = block(2, &["tx3", "tx3"]);
+        assert_eq!(index.insert_block(&repeated).unwrap_err().location.block_hash, repeated.block_hash);
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn a_branch_may_repeat_only_what_it_replaces() {
+        let mut index = TransactionIndex::new();
+        let settled = block(1, &["tx1"]);
+        let orphaned = block(2, &["tx2"]);
+        index.insert_block(&settled).unwrap();
+        index.insert_block(&orphaned).unwrap();
+
+        let branch = vec![block(2, &["tx2", "tx3"]), block(3, &["tx4"])];
+        assert!(index.find_duplicate(&branch, std::slice::from_ref(&orphaned)).is_none());
+
+        let branch = vec![block(2, &["tx3"]), block(3, &["tx1"])];
+        let (found_in, duplicate) = index.find_duplicate(&branch, std::slice::from_ref(&orphaned)).unwrap();
+        assert_eq!(found_in.block_hash, branch[1].block_hash);
+        assert_eq!(duplicate.location.block_hash, settled.block_hash);
+    }
+}
+
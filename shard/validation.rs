@@ -0,0 +1,258 @@
+use crate::crypto::keys::SignatureError;
+use crate::shard::ledger::{ExecutionError, Ledger};
+use crate::shard::shard::Transaction;
+use crate::shard::tx_index::TransactionIndex;
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+pub const DEFAULT_MAX_TRANSACTION_BYTES: usize = 1024;
+pub const DEFAULT_MAX_ID_LEN: usize = 64;
+pub const DEFAULT_MIN_AMOUNT: u64 = 1;
+pub const DEFAULT_MAX_AMOUNT: u64 = 1_000_000;
+
+// Why a transaction was refused admission to a block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectionReason {
+    TooLarge { size: usize, max: usize },
+    InvalidId(String),
+    AmountOutOfBounds { amount: u64, min: u64, max: u64 },
+    Duplicate,
+    WrongShard { shard_id: usize, from_shard: usize, to_shard: usize },
+    StaleNonce { expected: u64, found: u64 },
+    InvalidSignature(SignatureError),
+    UnprovenDebit, // a cross-shard credit without valid proof the sender was debited
+}
+
+// What the rules can see of the shard while a batch is being checked.
+pub struct ValidationContext<'a> {
+    pub shard_id: usize,
+    pub ledger: &'a Ledger,
+    pub processed_transactions: &'a HashSet<String>,
+    pub tx_index: &'a TransactionIndex, // every transaction on the chain, settled blocks included
+    pub batch_ids: &'a HashSet<String>, // ids already accepted into the current batch
+}
+
+pub trait TransactionValidator: Debug + Send {
+    fn validate(&self, tx: &Transaction, context: &ValidationContext) -> Result<(), RejectionReason>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaxSize {
+    pub max_bytes: usize,
+}
+
+impl TransactionValidator for MaxSize {
+    fn validate(&self, tx: &Transaction, _context: &ValidationContext) -> Result<(), RejectionReason> {
+        let size = tx.size();
+        if size > self.max_bytes {
+            return Err(RejectionReason::TooLarge { size, max: self.max_bytes });
+        }
+        Ok(())
+    }
+}
+
+// Ids are non-empty ASCII alphanumerics, '-' or '_', no longer than `max_len`.
+#[derive(Debug, Clone, Copy)]
+pub struct IdFormat {
+    pub max_len: usize,
+}
+
+impl TransactionValidator for IdFormat {
+    fn validate(&self, tx: &Transaction, _context: &ValidationContext) -> Result<(), RejectionReason> {
+        let well_formed = !tx.id.is_empty()
+            && tx.id.len() <= self.max_len
+            && tx.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !well_formed {
+            return Err(RejectionReason::InvalidId(tx.id.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AmountBounds {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl TransactionValidator for AmountBounds {
+    fn validate(&self, tx: &Transaction, _context: &ValidationContext) -> Result<(), RejectionReason> {
+        if tx.amount < self.min || tx.amount > self.max {
+            return Err(RejectionReason::AmountOutOfBounds {
+                amount: tx.amount,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NoDuplicates;
+
+impl TransactionValidator for NoDuplicates {
+    fn validate(&self, tx: &Transaction, context: &ValidationContext) -> Result<(), RejectionReason> {
+        if context.processed_transactions.contains(&tx.id)
+            || context.tx_index.get(&tx.id).is_some()
+            || context.batch_ids.contains(&tx.id)
+        {
+            return Err(RejectionReason::Duplicate);
+        }
+        Ok(())
+    }
+}
+
+// A shard only records transactions that leave from or arrive at it.
+#[derive(Debug, Clone, Copy)]
+pub struct ShardOwnership;
+
+impl TransactionValidator for ShardOwnership {
+    fn validate(&self, tx: &Transaction, context: &ValidationContext) -> Result<(), RejectionReason> {
+        if tx.from_shard != context.shard_id && tx.to_shard != context.shard_id {
+            return Err(RejectionReason::WrongShard {
+                shard_id: context.shard_id,
+                from_shard: tx.from_shard,
+                to_shard: tx.to_shard,
+            });
+        }
+        Ok(())
+    }
+}
+
+// A nonce the sending account has already used can never execute. Nonces
+// ahead of the sequence are left alone; they wait for the ones before them.
+#[derive(Debug, Clone, Copy)]
+pub struct FreshNonce;
+
+impl TransactionValidator for FreshNonce {
+    fn validate(&self, tx: &Transaction, context: &ValidationContext) -> Result<(), RejectionReason> {
+        match context.ledger.check_nonce(tx, context.shard_id) {
+            Err(ExecutionError::StaleNonce { expected, found, .. }) => Err(RejectionReason::StaleNonce { expected, found }),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Runs its rules in registration order and stops at the first rejection. A
+// pipeline is itself a validator, so pipelines can be nested.
+#[derive(Debug, Default)]
+pub struct ValidationPipeline {
+    rules: Vec<Box<dyn TransactionValidator>>,
+}
+
+impl ValidationPipeline {
+    pub fn new() -> Self {
+        ValidationPipeline { rules: Vec::new() }
+    }
+
+    pub fn with_rule(mut self, rule: impl TransactionValidator + 'static) -> Self {
+        self.register(Box::new(rule));
+        self
+    }
+
+    pub fn register(&mut self, rule: Box<dyn TransactionValidator>) {
+        self.rules.push(rule);
+    }
+}
+
+impl TransactionValidator for ValidationPipeline {
+    fn validate(&self, tx: &Transaction, context: &ValidationContext) -> Result<(), RejectionReason> {
+        self.rules.iter().try_for_each(|rule| rule.validate(tx, context))
+    }
+}
+
+pub fn default_pipeline() -> ValidationPipeline {
+    ValidationPipeline::new()
+        .with_rule(MaxSize { max_bytes: DEFAULT_MAX_TRANSACTION_BYTES })
+        .with_rule(IdFormat { max_len: DEFAULT_MAX_ID_LEN })
+        .with_rule(AmountBounds { min: DEFAULT_MIN_AMOUNT, max: DEFAULT_MAX_AMOUNT })
+        .with_rule(NoDuplicates)
+        .with_rule(ShardOwnership)
+        .with_rule(FreshNonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::block::{Block, BlockHeader};
+    use crate::poh::entry::PohEntry;
+    use crate::poh::hash::Hash;
+    use crate::shard::shard::TransactionStatus;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn transfer(id: &str, from_shard: usize, to_shard: usize, nonce: u64) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            from_account: "alice".to_string(),
+            to_account: "bob".to_string(),
+            amount: 10,
+            fee: 1,
+            nonce,
+            from_shard,
+            to_shard,
+            public_key: Default::default(),
+            signature: Default::default(),
+            status: TransactionStatus::Pending,
+        }
+    }
+
+    // Shard 1, where "alice" has used nonces 0 and 1, "tx0" is already on the
+    // chain and "tx-settled" is in a block settled before the last checkpoint.
+    fn check(rule: &dyn TransactionValidator, tx: &Transaction, batch: &[&str]) -> Result<(), RejectionReason> {
+        let ledger = Ledger::from_snapshot(HashMap::new(), 0, HashMap::from([("alice".to_string(), 2)]));
+        let processed = HashSet::from(["tx0".to_string()]);
+        let entry = PohEntry::new(1, vec!["tx-settled".to_string()], Vec::new(), Hash::default(), &Hash::default(), Utc::now());
+        let header = BlockHeader::new(1, Hash::default(), 1, 0, 0, Hash::default(), 0);
+        let mut tx_index = TransactionIndex::new();
+        tx_index.insert_block(&Block::new(header, Hash::default(), vec![entry], Vec::new())).unwrap();
+        let batch_ids = batch.iter().map(|id| id.to_string()).collect();
+        let context = ValidationContext {
+            shard_id: 1,
+            ledger: &ledger,
+            processed_transactions: &processed,
+            tx_index: &tx_index,
+            batch_ids: &batch_ids,
+        };
+        rule.validate(tx, &context)
+    }
+
+    #[test]
+    fn duplicates_of_the_chain_or_the_batch_are_rejected() {
+        assert_eq!(check(&NoDuplicates, &transfer("tx0", 1, 1, 2), &[]), Err(RejectionReason::Duplicate));
+        assert_eq!(check(&NoDuplicates, &transfer("tx-settled", 1, 1, 2), &[]), Err(RejectionReason::Duplicate));
+        assert_eq!(check(&NoDuplicates, &transfer("tx1", 1, 1, 2), &["tx1"]), Err(RejectionReason::Duplicate));
+        assert_eq!(check(&NoDuplicates, &transfer("tx1", 1, 1, 2), &["tx2"]), Ok(()));
+    }
+
+    #[test]
+    fn only_transfers_touching_the_shard_are_accepted() {
+        assert_eq!(
+            check(&ShardOwnership, &transfer("tx1", 2, 3, 2), &[]),
+            Err(RejectionReason::WrongShard { shard_id: 1, from_shard: 2, to_shard: 3 })
+        );
+        assert_eq!(check(&ShardOwnership, &transfer("tx1", 2, 1, 2), &[]), Ok(()));
+    }
+
+    #[test]
+    fn used_nonces_are_rejected_and_later_ones_wait() {
+        assert_eq!(
+            check(&FreshNonce, &transfer("tx1", 1, 1, 1), &[]),
+            Err(RejectionReason::StaleNonce { expected: 2, found: 1 })
+        );
+        assert_eq!(check(&FreshNonce, &transfer("tx1", 1, 1, 5), &[]), Ok(()));
+    }
+
+    #[test]
+    fn pipeline_reports_the_first_rule_that_rejects() {
+        let pipeline = ValidationPipeline::new()
+            .with_rule(IdFormat { max_len: 4 })
+            .with_rule(NoDuplicates);
+        assert_eq!(
+            check(&pipeline, &transfer("tx0-long", 1, 1, 2), &[]),
+            Err(RejectionReason::InvalidId("tx0-long".to_string()))
+        );
+        assert_eq!(check(&pipeline, &transfer("tx0", 1, 1, 2), &[]), Err(RejectionReason::Duplicate));
+    }
+}
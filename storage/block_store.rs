@@ -0,0 +1,286 @@
+use crate::block::block::Block;
+use crate::codec::codec::{Decode, Encode};
+use crate::poh::hash::{Hash, HASH_BYTES};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Each record is `[payload length: u64 LE][SHA-256 of payload][payload]`, where
+// the payload is the block's canonical encoding.
+const RECORD_HEADER_BYTES: u64 = 8 + HASH_BYTES as u64;
+
+// Why the record at some offset could not be read back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecordFault {
+    Incomplete, // the file ends inside it: a write torn by a crash
+    Corrupt,    // complete, but fails its checksum or does not decode
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RecordLocation {
+    offset: u64,
+    len: u64, // whole record, header included
+}
+
+// Append-only block log for one shard. Records are only ever added at the end
+// of the segment file; the number and hash indexes are rebuilt by scanning the
+// file when it is opened.
+#[derive(Debug)]
+pub struct BlockStore {
+    path: PathBuf,
+    file: File,
+    end: u64,
+    by_number: BTreeMap<u64, RecordLocation>,
+    by_hash: HashMap<Hash, u64>,
+}
+
+impl BlockStore {
+    pub fn segment_path(dir: &Path, shard_id: usize) -> PathBuf {
+        dir.join(format!("shard-{}.blocks", shard_id))
+    }
+
+    // Open (or create) the shard's segment file and recover it. Scanning stops
+    // at the first record that cannot be read. If the file ends inside it, the
+    // write was torn by a crash and the tail is cut off. Any other bad record
+    // is set aside with everything after it, as in `set_aside_from`, since it
+    // may be followed by blocks worth recovering by hand.
+    pub fn open(dir: &Path, shard_id: usize) -> io::Result<(Self, Vec<Block>)> {
+        fs::create_dir_all(dir)?;
+        let path = Self::segment_path(dir, shard_id);
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut store = BlockStore {
+            path,
+            file,
+            end: 0,
+            by_number: BTreeMap::new(),
+            by_hash: HashMap::new(),
+        };
+
+        let mut blocks: Vec<Block> = Vec::new();
+        while store.end < data.len() as u64 {
+            let fault = match decode_record(&data[store.end as usize..]) {
+                Ok((block, len)) => {
+                    store.index(&block, len);
+                    blocks.push(block);
+                    continue;
+                }
+                Err(fault) => fault,
+            };
+
+            let last = blocks.last().map(|block| block.header.block_number).unwrap_or(0);
+            match fault {
+                RecordFault::Incomplete => println!(
+                    "Block store {}: truncating {} bytes of torn tail after block #{}",
+                    store.path.display(),
+                    data.len() as u64 - store.end,
+                    last
+                ),
+                RecordFault::Corrupt => {
+                    let aside = store.keep_copy(last + 1)?;
+                    println!(
+                        "Block store {}: the record after block #{} is corrupt. Continuing without it and everything after it; the log was kept as {}.",
+                        store.path.display(),
+                        last,
+                        aside.display()
+                    );
+                }
+            }
+            store.file.set_len(store.end)?;
+            store.file.sync_all()?;
+            break;
+        }
+
+        Ok((store, blocks))
+    }
+
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let payload = block.to_bytes();
+        let mut record = Vec::with_capacity(RECORD_HEADER_BYTES as usize + payload.len());
+        record.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        record.extend_from_slice(Hash::digest(&payload).as_ref());
+        record.extend_from_slice(&payload);
+
+        // a partly written record would sit in front of every later append
+        if let Err(e) = self.file.write_all(&record).and_then(|()| self.file.sync_data()) {
+            let _ = self.file.set_len(self.end);
+            return Err(e);
+        }
+        self.index(block, record.len() as u64);
+        Ok(())
+    }
+
+    pub fn get(&self, block_number: u64) -> io::Result<Option<Block>> {
+        match self.by_number.get(&block_number) {
+            Some(location) => self.read(*location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_by_hash(&self, block_hash: &Hash) -> io::Result<Option<Block>> {
+        match self.by_hash.get(block_hash) {
+            Some(block_number) => self.get(*block_number),
+            None => Ok(None),
+        }
+    }
+
+    // Drop every block numbered `block_number` or higher.
+    pub fn truncate_from(&mut self, block_number: u64) -> io::Result<()> {
+        let end = match self.by_number.range(block_number..).next() {
+            Some((_, location)) => location.offset,
+            None => return Ok(()),
+        };
+
+        self.file.set_len(end)?;
+        self.file.sync_all()?;
+        self.end = end;
+        self.by_number.split_off(&block_number);
+        let by_number = &self.by_number;
+        self.by_hash.retain(|_, number| by_number.contains_key(number));
+        Ok(())
+    }
+
+    // Keep a copy of the whole log beside it, then drop every block numbered
+    // `block_number` or higher. For blocks that are intact on disk but can no
+    // longer be accepted, so they are still there to inspect or recover.
+    pub fn set_aside_from(&mut self, block_number: u64) -> io::Result<PathBuf> {
+        let aside = self.keep_copy(block_number)?;
+        self.truncate_from(block_number)?;
+        Ok(aside)
+    }
+
+    // Copy the log to an unused `.rejected-<block_number>` name beside it.
+    fn keep_copy(&self, block_number: u64) -> io::Result<PathBuf> {
+        let mut aside = self.path.with_extension(format!("blocks.rejected-{}", block_number));
+        let mut attempt = 1;
+        while aside.exists() {
+            aside = self.path.with_extension(format!("blocks.rejected-{}.{}", block_number, attempt));
+            attempt += 1;
+        }
+        fs::copy(&self.path, &aside)?;
+        Ok(aside)
+    }
+
+    fn index(&mut self, block: &Block, len: u64) {
+        let number = block.header.block_number;
+        self.by_number.insert(number, RecordLocation { offset: self.end, len });
+        self.by_hash.insert(block.block_hash, number);
+        self.end += len;
+    }
+
+    fn read(&self, location: RecordLocation) -> io::Result<Block> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut record = vec![0u8; location.len as usize];
+        file.read_exact(&mut record)?;
+        decode_record(&record)
+            .map(|(block, _)| block)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "corrupt block record"))
+    }
+}
+
+// Decode the record at the start of `data`, which runs to the end of the file,
+// returning the block and the length of the whole record.
+fn decode_record(data: &[u8]) -> Result<(Block, u64), RecordFault> {
+    if (data.len() as u64) < RECORD_HEADER_BYTES {
+        return Err(RecordFault::Incomplete);
+    }
+    let payload_len = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let checksum = &data[8..RECORD_HEADER_BYTES as usize];
+    let record_len = RECORD_HEADER_BYTES.saturating_add(payload_len);
+    if (data.len() as u64) < record_len {
+        return Err(RecordFault::Incomplete);
+    }
+
+    let payload = &data[RECORD_HEADER_BYTES as usize..record_len as usize];
+    if Hash::digest(payload).as_ref() != checksum {
+        return Err(RecordFault::Corrupt);
+    }
+    let block = Block::from_bytes(payload).map_err(|_| RecordFault::Corrupt)?;
+    Ok((block, record_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::block::BlockHeader;
+
+    // A fresh directory under the system temp dir for one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pohts-block-store-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn chain(count: u64) -> Vec<Block> {
+        let mut previous_hash = Hash::default();
+        (1..=count)
+            .map(|number| {
+                let header = BlockHeader::new(number, previous_hash, 1, 0, 0, Hash::default(), 1_700_000_000);
+                let block = Block::new(header, Hash::default(), Vec::new(), Vec::new());
+                previous_hash = block.block_hash;
+                block
+            })
+            .collect()
+    }
+
+    fn stored(dir: &Path, blocks: &[Block]) -> PathBuf {
+        let (mut store, _) = BlockStore::open(dir, 1).unwrap();
+        for block in blocks {
+            store.append(block).unwrap();
+        }
+        BlockStore::segment_path(dir, 1)
+    }
+
+    fn numbers(blocks: &[Block]) -> Vec<u64> {
+        blocks.iter().map(|block| block.header.block_number).collect()
+    }
+
+    #[test]
+    fn torn_tail_is_cut_off() {
+        let dir = scratch_dir("torn");
+        let blocks = chain(4);
+        let path = stored(&dir, &blocks[..3]);
+        let intact = fs::metadata(&path).unwrap().len();
+
+        // a crash part way through writing block #4
+        let (mut store, _) = BlockStore::open(&dir, 1).unwrap();
+        store.append(&blocks[3]).unwrap();
+        drop(store);
+        let torn = intact + RECORD_HEADER_BYTES + 5;
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(torn).unwrap();
+
+        let (mut store, recovered) = BlockStore::open(&dir, 1).unwrap();
+        assert_eq!(numbers(&recovered), vec![1, 2, 3]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        store.append(&blocks[3]).unwrap();
+        assert_eq!(store.get(4).unwrap().unwrap().block_hash, blocks[3].block_hash);
+        assert_eq!(numbers(&BlockStore::open(&dir, 1).unwrap().1), vec![1, 2, 3, 4]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn corrupt_record_is_set_aside_with_everything_after_it() {
+        let dir = scratch_dir("corrupt");
+        let blocks = chain(3);
+        let path = stored(&dir, &blocks);
+        let original = fs::read(&path).unwrap();
+
+        // flip a payload byte of block #2
+        let second = RECORD_HEADER_BYTES as usize + blocks[0].to_bytes().len() + RECORD_HEADER_BYTES as usize;
+        let mut damaged = original.clone();
+        damaged[second + 3] ^= 0xff;
+        fs::write(&path, &damaged).unwrap();
+
+        let (store, recovered) = BlockStore::open(&dir, 1).unwrap();
+        assert_eq!(numbers(&recovered), vec![1]);
+        assert!(store.get(2).unwrap().is_none());
+        assert_eq!(fs::read(path.with_extension("blocks.rejected-2")).unwrap(), damaged);
+        assert_eq!(fs::metadata(&path).unwrap().len(), second as u64 - RECORD_HEADER_BYTES);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,2 @@
+pub mod block_store;
+pub mod snapshot_store;
@@ -0,0 +1,203 @@
+use crate::codec::codec::{Decode, Encode};
+use crate::poh::hash::{Hash, HASH_BYTES};
+use crate::shard::shard::Checkpoint;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SNAPSHOT_RETENTION: usize = 3;
+
+// Snapshot file layout: `[magic][format version][SHA-256 of payload][payload]`,
+// where the payload is the checkpoint's canonical encoding.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"PSNP";
+const SNAPSHOT_FORMAT_VERSION: u8 = 1;
+const SNAPSHOT_HEADER_BYTES: usize = SNAPSHOT_MAGIC.len() + 1 + HASH_BYTES;
+
+// Checkpoint snapshots for one shard, one file per block height. Only the
+// newest `retention` snapshots are kept.
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    shard_id: usize,
+    retention: usize,
+}
+
+impl SnapshotStore {
+    pub fn open(dir: &Path, shard_id: usize, retention: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(SnapshotStore {
+            dir: dir.to_path_buf(),
+            shard_id,
+            retention: retention.max(1),
+        })
+    }
+
+    fn prefix(&self) -> String {
+        format!("shard-{}-", self.shard_id)
+    }
+
+    fn path_for(&self, block_height: u64) -> PathBuf {
+        // zero-padded so file names sort by height
+        self.dir.join(format!("{}{:020}.snapshot", self.prefix(), block_height))
+    }
+
+    // Write the snapshot under a temporary name and rename it into place, so a
+    // crash never leaves a half-written file under a real snapshot name.
+    pub fn save(&self, checkpoint: &Checkpoint) -> io::Result<PathBuf> {
+        let payload = checkpoint.to_bytes();
+        let mut contents = Vec::with_capacity(SNAPSHOT_HEADER_BYTES + payload.len());
+        contents.extend_from_slice(SNAPSHOT_MAGIC);
+        contents.push(SNAPSHOT_FORMAT_VERSION);
+        contents.extend_from_slice(Hash::digest(&payload).as_ref());
+        contents.extend_from_slice(&payload);
+
+        let path = self.path_for(checkpoint.block_height);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &contents)?;
+        fs::File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+
+        self.prune()?;
+        Ok(path)
+    }
+
+    // The newest snapshot that passes its checksum and decodes. Damaged files
+    // are skipped, falling back to older snapshots.
+    pub fn load_latest(&self) -> io::Result<Option<Checkpoint>> {
+        for path in self.snapshot_paths()?.iter().rev() {
+            match read_snapshot(path) {
+                Ok(checkpoint) if checkpoint.shard_id == self.shard_id => return Ok(Some(checkpoint)),
+                Ok(_) => println!("Snapshot {} belongs to another shard, skipping", path.display()),
+                Err(e) => println!("Snapshot {} is unusable ({}), skipping", path.display(), e),
+            }
+        }
+        Ok(None)
+    }
+
+    // Delete the snapshots of blocks above `block_height`, once a reorg has
+    // replaced those blocks and a restart must not come back to them.
+    pub fn remove_above(&self, block_height: u64) -> io::Result<()> {
+        let limit = self.path_for(block_height);
+        for path in self.snapshot_paths()?.into_iter().filter(|path| *path > limit) {
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    // Snapshot files for this shard, oldest first.
+    fn snapshot_paths(&self) -> io::Result<Vec<PathBuf>> {
+        let prefix = self.prefix();
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext == "snapshot")
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with(&prefix))
+            })
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let paths = self.snapshot_paths()?;
+        let excess = paths.len().saturating_sub(self.retention);
+        for path in &paths[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_snapshot(path: &Path) -> io::Result<Checkpoint> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let contents = fs::read(path)?;
+    if contents.len() < SNAPSHOT_HEADER_BYTES || &contents[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+        return Err(invalid("not a snapshot file".to_string()));
+    }
+    let version = contents[SNAPSHOT_MAGIC.len()];
+    if version != SNAPSHOT_FORMAT_VERSION {
+        return Err(invalid(format!("unsupported snapshot version {}", version)));
+    }
+
+    let checksum = &contents[SNAPSHOT_MAGIC.len() + 1..SNAPSHOT_HEADER_BYTES];
+    let payload = &contents[SNAPSHOT_HEADER_BYTES..];
+    if Hash::digest(payload).as_ref() != checksum {
+        return Err(invalid("checksum mismatch".to_string()));
+    }
+    Checkpoint::from_bytes(payload).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::verifier::ChainTip;
+    use crate::shard::ledger::Ledger;
+    use std::collections::HashSet;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pohts-snapshots-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn checkpoint(block_height: u64) -> Checkpoint {
+        let mut ledger = Ledger::new();
+        ledger.mint("alice", block_height * 10).unwrap();
+        let tip = ChainTip {
+            block_number: block_height,
+            block_hash: Hash::digest(&block_height.to_le_bytes()),
+            poh_end_hash: Hash::default(),
+        };
+        Checkpoint::new(1, tip, &ledger, Vec::new(), HashSet::new())
+    }
+
+    fn heights(store: &SnapshotStore) -> Vec<u64> {
+        store
+            .snapshot_paths()
+            .unwrap()
+            .iter()
+            .map(|path| read_snapshot(path).unwrap().block_height)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_only_the_newest_snapshots() {
+        let dir = scratch_dir("retention");
+        let store = SnapshotStore::open(&dir, 1, 2).unwrap();
+        for height in [5, 10, 15] {
+            store.save(&checkpoint(height)).unwrap();
+        }
+        assert_eq!(heights(&store), vec![10, 15]);
+        assert_eq!(store.load_latest().unwrap().unwrap().block_height, 15);
+
+        store.remove_above(10).unwrap();
+        assert_eq!(heights(&store), vec![10]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn damaged_snapshots_fall_back_to_older_ones() {
+        let dir = scratch_dir("fallback");
+        let store = SnapshotStore::open(&dir, 1, 3).unwrap();
+        for height in [5, 10, 15] {
+            store.save(&checkpoint(height)).unwrap();
+        }
+        let mut flipped = fs::read(store.path_for(15)).unwrap();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0xff;
+        fs::write(store.path_for(15), flipped).unwrap();
+        fs::write(store.path_for(10), b"PSNP").unwrap();
+
+        let restored = store.load_latest().unwrap().unwrap();
+        assert_eq!(restored.block_height, 5);
+        assert_eq!(restored.state_root, checkpoint(5).state_root);
+
+        // another shard's snapshots are never picked up
+        assert!(SnapshotStore::open(&dir, 2, 3).unwrap().load_latest().unwrap().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::crypto::keys;
+use crate::poh::hash::Hash;
+use std::collections::HashMap;
+
 #[derive(Debug)]
 pub struct Validator {
     pub id: usize,
@@ -10,6 +14,7 @@ pub struct Validator {
     pub epochs_active: usize,  // number of epochs the validator has been active
     pub penalized: bool,       // whether the validator has been penalized
     pub final_vote_weight: f64, // the weight used in consensus
+    pub account: String,       // address that block fees are paid to
 }
 
 impl Validator {
@@ -25,16 +30,20 @@ impl Validator {
             epochs_active: 1,  // 1 to prevent division by zero issues in the early epochs
             penalized: false,
             final_vote_weight,
+            // simulated validators are paid to fixed test keys
+            account: keys::test_keypair(&format!("validator{}-{}", shard_id, id)).address(),
         }
     }
 
     // Adjust the final vote weight based on dynamic conditions
+    #[allow(dead_code)]
     pub fn adjust_weight(&mut self, factor: f64) {
         self.final_vote_weight *= factor;
         self.final_vote_weight = self.final_vote_weight.clamp(0.3, 1.0); // Clamp to a minimum of 0.3 and maximum of 1.0
         println!("Validator {} adjusted weight to {:.2}", self.id, self.final_vote_weight);
     }
 
+    #[allow(dead_code)]
     pub fn cast_vote(&mut self, is_successful: bool, response_time: u128, aligns_with_consensus: bool) {
         self.votes_cast += 1;
 
@@ -100,6 +109,7 @@ impl Validator {
         final_weight
     }
 
+    #[allow(dead_code)]
     pub fn validate_transaction(&self, transaction_id: &str, current_epoch: usize) -> bool {
         println!(
             "Validator {} (Shard {}) is validating transaction {} for epoch {}",
@@ -129,4 +139,27 @@ impl ValidatorPerformance {
             response_time: validator.average_response_time_ms,
         }
     }
+
+    pub fn contribution_factor(&self) -> f64 {
+        self.honesty_score * (1.0 / (self.response_time + 1.0))
+    }
+}
+
+// Digest of a validator performance set, taken in validator id order so every
+// node commits the same bytes regardless of map iteration order.
+pub fn performance_digest(validator_performance: &HashMap<usize, ValidatorPerformance>) -> Hash {
+    let mut performances: Vec<&ValidatorPerformance> = validator_performance.values().collect();
+    performances.sort_by_key(|performance| performance.id);
+
+    let encoded: Vec<[u8; 16]> = performances
+        .iter()
+        .map(|performance| {
+            let mut bytes = [0u8; 16];
+            bytes[..8].copy_from_slice(&(performance.id as u64).to_le_bytes());
+            bytes[8..].copy_from_slice(&performance.contribution_factor().to_bits().to_le_bytes());
+            bytes
+        })
+        .collect();
+    let parts: Vec<&[u8]> = encoded.iter().map(|bytes| bytes.as_slice()).collect();
+    Hash::hashv(&parts)
 }